@@ -1,3 +1,7 @@
+// Shared between `build.rs`, which bakes `device.properties` into `device_properties.bin`, and
+// the library, which decodes that file and parses further profiles at runtime. The includer is
+// expected to import `Ini`, `Message`, `Cursor`, `HashMap`, serde and the protobuf types.
+
 #[derive(Serialize, Deserialize, Debug)]
 struct EncodedDeviceProperties {
     pub device_configuration: Vec<u8>,
@@ -5,9 +9,12 @@ struct EncodedDeviceProperties {
     pub extra_info: HashMap<String, String>,
 }
 
-#[derive(Debug)]
+/// A device gpapi can present itself as, as described by a `device.properties` file.
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
-struct DeviceProperties {
+pub struct DeviceProperties {
+    /// The name the device is known under, e.g. `px_7a`.
+    pub codename: String,
     pub device_configuration: DeviceConfigurationProto,
     pub android_checkin: AndroidCheckinProto,
     pub extra_info: HashMap<String, String>,
@@ -27,17 +34,244 @@ impl EncodedDeviceProperties {
         }
     }
 
-    pub fn to_decoded(self) -> DeviceProperties {
+    pub fn to_decoded(&self, codename: String) -> DeviceProperties {
         DeviceProperties {
+            codename,
             device_configuration: DeviceConfigurationProto::decode(&mut Cursor::new(
-                &self.device_configuration.clone(),
-            ))
-            .unwrap(),
-            android_checkin: AndroidCheckinProto::decode(&mut Cursor::new(
-                &self.android_checkin.clone(),
+                &self.device_configuration,
             ))
             .unwrap(),
-            extra_info: self.extra_info,
+            android_checkin: AndroidCheckinProto::decode(&mut Cursor::new(&self.android_checkin))
+                .unwrap(),
+            extra_info: self.extra_info.clone(),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl DeviceProperties {
+    fn to_encoded(&self) -> EncodedDeviceProperties {
+        EncodedDeviceProperties::new(
+            self.device_configuration.encode_to_vec(),
+            self.android_checkin.encode_to_vec(),
+            self.extra_info.clone(),
+        )
+    }
+
+    /// The `device.properties` keys the profile lacks, which Play needs to filter apps for the
+    /// device faithfully. Empty if the profile is complete.
+    ///
+    /// Parsing already rejects profiles lacking one of `REQUIRED_BUILD_KEYS`, which logging in
+    /// cannot do without. The other keys fall back to empty values, as for some bundled profiles.
+    pub fn validate(&self) -> Vec<String> {
+        let build = self.android_checkin.build.clone().unwrap_or_default();
+        let config = &self.device_configuration;
+        let present = [
+            ("Build.DEVICE", build.device.is_some()),
+            ("Build.HARDWARE", build.product.is_some()),
+            ("Build.PRODUCT", build.build_product.is_some()),
+            ("Build.MODEL", build.model.is_some()),
+            ("Build.FINGERPRINT", build.id.is_some()),
+            ("Build.VERSION.SDK_INT", build.sdk_version.is_some()),
+            ("GSF.version", build.google_services.is_some()),
+            ("Screen.Density", config.screen_density.is_some()),
+            ("GL.Version", config.gl_es_version.is_some()),
+            ("Platforms", !config.native_platform.is_empty()),
+            ("Features", !config.device_feature.is_empty()),
+            ("Locales", !config.system_supported_locale.is_empty()),
+            ("GL.Extensions", !config.gl_extension.is_empty()),
+            ("SharedLibraries", !config.system_shared_library.is_empty()),
+        ];
+        let extra_info = EXTRA_INFO_KEYS.iter().map(|key| {
+            (
+                *key,
+                self.extra_info.get(*key).is_some_and(|value| !value.is_empty()),
+            )
+        });
+
+        present
+            .into_iter()
+            .chain(extra_info)
+            .filter(|(_, present)| !present)
+            .map(|(key, _)| format!("missing '{}'", key))
+            .collect()
+    }
+}
+
+/// The build keys gpapi cannot log in without, see `DeviceProperties::validate`.
+const REQUIRED_BUILD_KEYS: [&str; 6] = [
+    "Build.DEVICE",
+    "Build.HARDWARE",
+    "Build.PRODUCT",
+    "Build.MODEL",
+    "Build.VERSION.SDK_INT",
+    "GSF.version",
+];
+
+/// The keys copied into `extra_info` for building request headers, empty if missing.
+const EXTRA_INFO_KEYS: [&str; 4] = [
+    "Build.ID",
+    "Vending.versionString",
+    "Vending.version",
+    "Build.VERSION.RELEASE",
+];
+
+/// The section configparser files keys under that precede any `[section]` header.
+const DEFAULT_SECTION: &str = "default";
+
+/// Parse every device of a `device.properties` file, keyed by codename. Fails only if the file
+/// itself is malformed, each device that cannot be parsed is reported on its own.
+///
+/// Sections are named after Aurora Store's device files, e.g. `[gplayapi_px_7a.properties]` for
+/// `px_7a`. A file without any section header describes a single device, which is named after
+/// its `Build.DEVICE`.
+#[allow(dead_code)]
+fn parse_device_properties(
+    content: &str,
+) -> Result<HashMap<String, Result<DeviceProperties, String>>, String> {
+    let mut config = Ini::new();
+    config.read(content.to_string())?;
+
+    let mut devices = HashMap::new();
+    for section in config.sections() {
+        let codename = if section == DEFAULT_SECTION {
+            config.get(&section, "Build.DEVICE").unwrap_or(section.clone())
+        } else {
+            section.replace("gplayapi_", "").replace(".properties", "")
+        };
+        let device = parse_device_section(&config, &section, codename.clone());
+        devices.insert(codename, device);
+    }
+    Ok(devices)
+}
+
+fn required(config: &Ini, section: &str, key: &str) -> Result<String, String> {
+    config
+        .get(section, key)
+        .ok_or_else(|| format!("missing '{}'", key))
+}
+
+fn int(config: &Ini, section: &str, key: &str) -> Result<Option<i32>, String> {
+    config
+        .getint(section, key)
+        .map(|value| value.map(|v| v as i32))
+        .map_err(|e| format!("'{}': {}", key, e))
+}
+
+fn boolean(config: &Ini, section: &str, key: &str) -> Result<Option<bool>, String> {
+    config
+        .getbool(section, key)
+        .map_err(|e| format!("'{}': {}", key, e))
+}
+
+/// A comma separated list, empty if the key is missing, see `DeviceProperties::validate`.
+fn list(config: &Ini, section: &str, key: &str) -> Vec<String> {
+    config
+        .get(section, key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// A feature entry, either just its name or `name=version`, e.g.
+/// `android.hardware.vulkan.version=4206592`.
+fn parse_feature(entry: &str) -> Result<DeviceFeature, String> {
+    let (name, value) = match entry.split_once('=') {
+        Some((name, version)) => {
+            let version = version
+                .trim()
+                .parse::<i32>()
+                .map_err(|e| format!("feature '{}': {}", entry, e))?;
+            (name.trim(), version)
         }
+        None => (entry, 0),
+    };
+    Ok(DeviceFeature {
+        name: Some(name.to_string()),
+        value: Some(value),
+    })
+}
+
+fn parse_device_section(
+    config: &Ini,
+    section: &str,
+    codename: String,
+) -> Result<DeviceProperties, String> {
+    for key in REQUIRED_BUILD_KEYS {
+        required(config, section, key)?;
+    }
+
+    let mut extra_info = HashMap::new();
+    for key in EXTRA_INFO_KEYS {
+        extra_info.insert(
+            key.to_string(),
+            config.get(section, key).unwrap_or_default(),
+        );
     }
+    if let Some(sim_operator) = config.get(section, "SimOperator") {
+        extra_info.insert("SimOperator".to_string(), sim_operator);
+    }
+
+    let android_build = AndroidBuildProto {
+        id: config.get(section, "Build.FINGERPRINT"),
+        product: config.get(section, "Build.HARDWARE"),
+        carrier: config.get(section, "Build.BRAND"),
+        radio: config.get(section, "Build.RADIO"),
+        bootloader: config.get(section, "Build.BOOTLOADER"),
+        device: config.get(section, "Build.DEVICE"),
+        sdk_version: int(config, section, "Build.VERSION.SDK_INT")?,
+        model: config.get(section, "Build.MODEL"),
+        manufacturer: config.get(section, "Build.MANUFACTURER"),
+        build_product: config.get(section, "Build.PRODUCT"),
+        client: config.get(section, "Client"),
+        ota_installed: Some(false),
+        google_services: int(config, section, "GSF.version")?,
+        ..Default::default()
+    };
+    let android_checkin = AndroidCheckinProto {
+        build: Some(android_build),
+        last_checkin_msec: Some(0),
+        cell_operator: config.get(section, "CellOperator"),
+        sim_operator: config.get(section, "SimOperator"),
+        roaming: config.get(section, "Roaming"),
+        user_number: Some(0),
+        ..Default::default()
+    };
+
+    let device_feature = list(config, section, "Features")
+        .iter()
+        .map(|entry| parse_feature(entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    let device_configuration = DeviceConfigurationProto {
+        touch_screen: int(config, section, "TouchScreen")?,
+        keyboard: int(config, section, "Keyboard")?,
+        navigation: int(config, section, "Navigation")?,
+        screen_layout: int(config, section, "ScreenLayout")?,
+        has_hard_keyboard: boolean(config, section, "HasHardKeyboard")?,
+        has_five_way_navigation: boolean(config, section, "HasFiveWayNavigation")?,
+        screen_density: int(config, section, "Screen.Density")?,
+        gl_es_version: int(config, section, "GL.Version")?,
+        system_shared_library: list(config, section, "SharedLibraries"),
+        native_platform: list(config, section, "Platforms"),
+        screen_width: int(config, section, "Screen.Width")?,
+        screen_height: int(config, section, "Screen.Height")?,
+        system_supported_locale: list(config, section, "Locales"),
+        gl_extension: list(config, section, "GL.Extensions"),
+        system_available_feature: device_feature
+            .iter()
+            .filter_map(|feature| feature.name.clone())
+            .collect(),
+        device_feature,
+        ..Default::default()
+    };
+
+    Ok(DeviceProperties {
+        codename,
+        device_configuration,
+        android_checkin,
+        extra_info,
+    })
 }
@@ -2,6 +2,9 @@ use std::error::Error as StdError;
 use std::fmt;
 use std::io::Error as IOError;
 
+use prost::DecodeError;
+use reqwest::header::{InvalidHeaderName, InvalidHeaderValue};
+
 #[derive(Debug)]
 pub enum ErrorKind {
     FileExists,
@@ -13,6 +16,25 @@ pub enum ErrorKind {
     PermissionDenied,
     InvalidResponse,
     LoginRequired,
+    /// Play left out something logging in depends on, e.g. the DFE cookie.
+    IncompleteLogin(String),
+    /// `accept_tos` was called without `login` having failed with `TermsOfService` first.
+    NoTermsOfService,
+    /// `restore_session` was given a session exported for another device or account.
+    SessionMismatch(String),
+    /// No bundled device profile has the given codename.
+    UnknownDevice(String),
+    /// A `device.properties` file could not be parsed.
+    InvalidDeviceProperties(String),
+    /// Play answered with an unexpected HTTP status.
+    HttpStatus(u16),
+    /// Play is throttling requests made by this account or device.
+    RateLimited,
+    /// Play reported an error message of its own, e.g. that the app is not available in the
+    /// country of the account.
+    Server(String),
+    Decode(DecodeError),
+    Network(reqwest::Error),
     IO(IOError),
     Str(String),
     Other(Box<dyn StdError + Send + Sync>),
@@ -41,6 +63,38 @@ impl From<IOError> for Error {
     }
 }
 
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Error {
+        Error {
+            kind: ErrorKind::Decode(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        Error {
+            kind: ErrorKind::Network(err),
+        }
+    }
+}
+
+impl From<InvalidHeaderName> for Error {
+    fn from(err: InvalidHeaderName) -> Error {
+        Error {
+            kind: ErrorKind::Other(Box::new(err)),
+        }
+    }
+}
+
+impl From<InvalidHeaderValue> for Error {
+    fn from(err: InvalidHeaderValue) -> Error {
+        Error {
+            kind: ErrorKind::Other(Box::new(err)),
+        }
+    }
+}
+
 impl From<Box<dyn StdError + Send + Sync>> for Error {
     fn from(err: Box<dyn StdError + Send + Sync>) -> Error {
         Error {
@@ -87,6 +141,22 @@ impl fmt::Display for Error {
             ErrorKind::PermissionDenied => write!(f, "Cannot create file: permission denied"),
             ErrorKind::InvalidResponse => write!(f, "Invalid response from the remote host"),
             ErrorKind::LoginRequired => write!(f, "Logging in is required for this action"),
+            ErrorKind::IncompleteLogin(missing) => {
+                write!(f, "Play did not return the {} while logging in", missing)
+            }
+            ErrorKind::NoTermsOfService => write!(f, "There are no terms of service to accept"),
+            ErrorKind::SessionMismatch(err) => write!(f, "Cannot restore session: {}", err),
+            ErrorKind::UnknownDevice(codename) => write!(f, "Unknown device '{}'", codename),
+            ErrorKind::InvalidDeviceProperties(err) => {
+                write!(f, "Invalid device properties: {}", err)
+            }
+            ErrorKind::HttpStatus(status) => {
+                write!(f, "Unexpected HTTP status {} from the remote host", status)
+            }
+            ErrorKind::RateLimited => write!(f, "Too many requests, try again later"),
+            ErrorKind::Server(message) => message.fmt(f),
+            ErrorKind::Decode(err) => write!(f, "Could not decode the response: {}", err),
+            ErrorKind::Network(err) => err.fmt(f),
             ErrorKind::IO(err) => err.fmt(f),
             ErrorKind::Str(err) => err.fmt(f),
             ErrorKind::Other(err) => err.fmt(f),
@@ -57,21 +57,28 @@
 
 mod consts;
 pub mod error;
+#[cfg(test)]
+mod mock_server;
 
 use bytes::Bytes;
 use prost::Message;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Url;
 use std::collections::HashMap;
-use std::error::Error;
+use std::fmt;
 use std::io::Cursor;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::sync::OnceLock;
 
 use crate::error::{Error as GpapiError, ErrorKind as GpapiErrorKind};
 
+use configparser::ini::Ini;
 use googleplay_protobuf::{
-    AcceptTosResponse, AndroidCheckinProto, AndroidCheckinRequest, AndroidCheckinResponse,
-    BulkDetailsRequest, BulkDetailsResponse, DetailsResponse, DeviceConfigurationProto,
-    ResponseWrapper, UploadDeviceConfigRequest, UploadDeviceConfigResponse,
+    AcceptTosResponse, AndroidBuildProto, AndroidCheckinProto, AndroidCheckinRequest,
+    AndroidCheckinResponse, BulkDetailsRequest, BulkDetailsResponse, DetailsResponse,
+    DeviceConfigurationProto, DeviceFeature, ResponseWrapper, TestingProgramInfo,
+    TestingProgramRequest, UploadDeviceConfigRequest, UploadDeviceConfigResponse,
 };
 
 use serde::{Deserialize, Serialize};
@@ -79,62 +86,514 @@ include!("device_properties.rs");
 
 static DEVICES_ENCODED: &[u8] = include_bytes!("device_properties.bin");
 
+/// The bundled device profiles, decoded once per process as every `Gpapi` looks its device up.
+fn bundled_devices() -> &'static HashMap<String, EncodedDeviceProperties> {
+    static DEVICES: OnceLock<HashMap<String, EncodedDeviceProperties>> = OnceLock::new();
+    DEVICES.get_or_init(|| bincode::deserialize(DEVICES_ENCODED).unwrap_or_default())
+}
+
+/// Whether `device_codename` names one of the bundled device profiles, i.e. whether `Gpapi::new`
+/// accepts it.
+pub fn has_device(device_codename: &str) -> bool {
+    bundled_devices().contains_key(device_codename)
+}
+
+/// An overview of a device profile, see `devices`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceSummary {
+    pub codename: String,
+    pub model: Option<String>,
+    pub manufacturer: Option<String>,
+    pub sdk_version: Option<i32>,
+    /// The native ABIs, most preferred first, e.g. `arm64-v8a`.
+    pub abis: Vec<String>,
+    pub screen_density: Option<i32>,
+    pub locales: Vec<String>,
+}
+
+/// Summaries of every bundled device profile, sorted by codename, to pick a codename for
+/// `Gpapi::new` from.
+pub fn devices() -> Vec<DeviceSummary> {
+    let mut devices: Vec<DeviceSummary> = bundled_devices()
+        .iter()
+        .map(|(codename, device)| device.to_decoded(codename.clone()).summary())
+        .collect();
+    devices.sort_by(|a, b| a.codename.cmp(&b.codename));
+    devices
+}
+
+impl DeviceProperties {
+    pub fn summary(&self) -> DeviceSummary {
+        let build = self.android_checkin.build.as_ref();
+        DeviceSummary {
+            codename: self.codename.clone(),
+            model: build.and_then(|build| build.model.clone()),
+            manufacturer: build.and_then(|build| build.manufacturer.clone()),
+            sdk_version: build.and_then(|build| build.sdk_version),
+            abis: self
+                .device_configuration
+                .native_platform
+                .iter()
+                .filter(|abi| !abi.is_empty())
+                .cloned()
+                .collect(),
+            screen_density: self.device_configuration.screen_density,
+            locales: self.device_configuration.system_supported_locale.clone(),
+        }
+    }
+
+    /// The bundled profile named `codename`, if there is one.
+    pub fn bundled(codename: &str) -> Option<Self> {
+        bundled_devices()
+            .get(codename)
+            .map(|device| device.to_decoded(codename.to_string()))
+    }
+
+    /// Parse every device of a `device.properties` file, keyed by codename, e.g. to pick one
+    /// of several profiles kept in a single file. Fails listing every device that cannot be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The file's content, either a list of `[gplayapi_<codename>.properties]`
+    ///   sections or the keys of a single device without any section header
+    pub fn parse_all(content: &str) -> Result<HashMap<String, Self>, GpapiError> {
+        let devices = parse_device_properties(content)
+            .map_err(|e| GpapiError::new(GpapiErrorKind::InvalidDeviceProperties(e)))?;
+
+        let mut codenames: Vec<&String> = devices.keys().collect();
+        codenames.sort();
+        let errors: Vec<String> = codenames
+            .into_iter()
+            .filter_map(|codename| match &devices[codename] {
+                Ok(_) => None,
+                Err(e) => Some(format!("device '{}': {}", codename, e)),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(GpapiError::new(GpapiErrorKind::InvalidDeviceProperties(
+                errors.join(", "),
+            )));
+        }
+
+        Ok(devices
+            .into_iter()
+            .filter_map(|(codename, device)| device.ok().map(|device| (codename, device)))
+            .collect())
+    }
+
+    /// Parse a `device.properties` file describing exactly one device.
+    pub fn parse(content: &str) -> Result<Self, GpapiError> {
+        let mut devices = Self::parse_all(content)?.into_values();
+        match (devices.next(), devices.next()) {
+            (Some(device), None) => Ok(device),
+            (None, _) => Err(GpapiError::new(GpapiErrorKind::InvalidDeviceProperties(
+                String::from("no device found"),
+            ))),
+            (Some(_), Some(_)) => Err(GpapiError::new(GpapiErrorKind::InvalidDeviceProperties(
+                String::from("more than one device found, use `parse_all` instead"),
+            ))),
+        }
+    }
+
+    /// Like `parse`, for content that has not been decoded as UTF-8 yet.
+    pub fn from_bytes(content: &[u8]) -> Result<Self, GpapiError> {
+        let content = std::str::from_utf8(content)
+            .map_err(|e| GpapiError::new(GpapiErrorKind::InvalidDeviceProperties(e.to_string())))?;
+        Self::parse(content)
+    }
+
+    /// Like `parse`, reading the content from `path`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &Path) -> Result<Self, GpapiError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// A build property requests depend on, or `InvalidDeviceProperties` naming its
+    /// `device.properties` key if the profile lacks it.
+    fn build_property<T>(
+        &self,
+        key: &str,
+        property: impl FnOnce(&AndroidBuildProto) -> Option<T>,
+    ) -> Result<T, GpapiError> {
+        self.android_checkin
+            .build
+            .as_ref()
+            .and_then(property)
+            .ok_or_else(|| {
+                GpapiError::new(GpapiErrorKind::InvalidDeviceProperties(format!(
+                    "missing '{}'",
+                    key
+                )))
+            })
+    }
+
+    /// An `extra_info` value, empty if the profile lacks it, as for some bundled profiles.
+    fn extra(&self, key: &str) -> &str {
+        self.extra_info
+            .get(key)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Start deriving a new profile from this one, see `DevicePropertiesBuilder`.
+    pub fn into_builder(self) -> DevicePropertiesBuilder {
+        DevicePropertiesBuilder::new(self)
+    }
+}
+
+/// Derives a device profile from an existing one, e.g. to see how Play filters an app for an
+/// x86_64 tablet or a newer SDK than any bundled device has. Anything not overridden is kept
+/// from the base, and the result is handed to `Gpapi::with_device`.
+#[derive(Debug, Clone)]
+pub struct DevicePropertiesBuilder {
+    device: DeviceProperties,
+}
+
+impl DevicePropertiesBuilder {
+    pub fn new(base: DeviceProperties) -> Self {
+        Self { device: base }
+    }
+
+    /// Start from the bundled profile named `codename`, or fail with `UnknownDevice`.
+    pub fn bundled(codename: &str) -> Result<Self, GpapiError> {
+        DeviceProperties::bundled(codename)
+            .map(Self::new)
+            .ok_or_else(|| GpapiError::new(GpapiErrorKind::UnknownDevice(codename.to_string())))
+    }
+
+    /// Rename the profile. Sessions are tied to the codename, so a profile that differs from
+    /// its base should not keep the base's name.
+    pub fn codename<S: Into<String>>(mut self, codename: S) -> Self {
+        self.device.codename = codename.into();
+        self
+    }
+
+    /// The native ABIs, most preferred first, e.g. `["x86_64", "x86"]`.
+    pub fn abis<I: IntoIterator<Item = S>, S: Into<String>>(mut self, abis: I) -> Self {
+        self.device.device_configuration.native_platform =
+            abis.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn sdk_version(mut self, sdk_version: i32) -> Self {
+        self.device
+            .android_checkin
+            .build
+            .get_or_insert_with(Default::default)
+            .sdk_version = Some(sdk_version);
+        self
+    }
+
+    /// The screen density in dpi, e.g. `420`.
+    pub fn screen_density(mut self, screen_density: i32) -> Self {
+        self.device.device_configuration.screen_density = Some(screen_density);
+        self
+    }
+
+    pub fn gl_extensions<I: IntoIterator<Item = S>, S: Into<String>>(
+        mut self,
+        gl_extensions: I,
+    ) -> Self {
+        self.device.device_configuration.gl_extension =
+            gl_extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The system features as `(name, version)` pairs, e.g.
+    /// `("android.hardware.vulkan.version", 4206592)`, replacing those of the base. Features
+    /// without a version, like `android.hardware.camera`, take version 0.
+    pub fn features<I: IntoIterator<Item = (S, i32)>, S: Into<String>>(
+        mut self,
+        features: I,
+    ) -> Self {
+        let features: Vec<DeviceFeature> = features
+            .into_iter()
+            .map(|(name, version)| DeviceFeature {
+                name: Some(name.into()),
+                value: Some(version),
+            })
+            .collect();
+        self.device.device_configuration.system_available_feature = features
+            .iter()
+            .filter_map(|feature| feature.name.clone())
+            .collect();
+        self.device.device_configuration.device_feature = features;
+        self
+    }
+
+    /// The supported locales, e.g. `["en", "de_DE"]`.
+    pub fn locales<I: IntoIterator<Item = S>, S: Into<String>>(mut self, locales: I) -> Self {
+        self.device.device_configuration.system_supported_locale =
+            locales.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn build(self) -> DeviceProperties {
+        self.device
+    }
+}
+
 pub type MainAPKDownloadURL = Option<String>;
 pub type SplitsDownloadInfo = Vec<(Option<String>, Option<String>)>;
 pub type AdditionalFilesDownloadInfo = Vec<(Option<String>, Option<String>)>;
-pub type DownloadInfo = (
+/// The shape `get_download_info` returned before `DownloadInfo` was introduced, see
+/// `DownloadInfo::into_legacy`.
+pub type LegacyDownloadInfo = (
     MainAPKDownloadURL,
     SplitsDownloadInfo,
     AdditionalFilesDownloadInfo,
 );
 
+/// Everything needed to download a release of an app, see `Gpapi::get_download_info`.
+///
+/// For some releases Play also offers a gzipped copy of each file, which is smaller to download
+/// but has to be decompressed afterwards. Its URL and size are given as `compressed_url` and
+/// `compressed_size` next to those of the file itself.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadInfo {
+    pub base: ApkFile,
+    pub splits: Vec<SplitFile>,
+    pub additional_files: Vec<AdditionalFile>,
+    /// Cookies Play requires to be sent along with every download URL of the release.
+    #[serde(default)]
+    pub cookies: Vec<DownloadCookie>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadCookie {
+    pub name: String,
+    pub value: String,
+}
+
+/// The base APK of a release.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApkFile {
+    pub url: Option<String>,
+    pub size: Option<i64>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    /// The gzipped copy of the file, if Play offers one, see `DownloadInfo`.
+    pub compressed_url: Option<String>,
+    pub compressed_size: Option<i64>,
+}
+
+/// A split APK of a release, e.g. `config.arm64_v8a`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitFile {
+    /// The split's name, without the `.apk` extension.
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub size: Option<i64>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    /// The gzipped copy of the split, if Play offers one.
+    pub compressed_url: Option<String>,
+    pub compressed_size: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdditionalFileType {
+    Main,
+    Patch,
+}
+
+impl fmt::Display for AdditionalFileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdditionalFileType::Main => write!(f, "main"),
+            AdditionalFileType::Patch => write!(f, "patch"),
+        }
+    }
+}
+
+/// An OBB expansion file shipped alongside a release.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdditionalFile {
+    pub file_type: AdditionalFileType,
+    /// The release the file was uploaded with, which may be older than the one downloaded.
+    pub version_code: i32,
+    /// The name Android expects the file under, e.g. `main.42.com.example.app.obb`.
+    pub filename: String,
+    pub url: Option<String>,
+    pub size: Option<i64>,
+    pub sha1: Option<String>,
+    /// The gzipped copy of the OBB file, if Play offers one.
+    pub compressed_url: Option<String>,
+    pub compressed_size: Option<i64>,
+}
+
+impl DownloadInfo {
+    /// The value of the `Cookie` header the download URLs have to be requested with, `None` if
+    /// Play did not ask for any cookies.
+    pub fn cookie_header(&self) -> Option<String> {
+        if self.cookies.is_empty() {
+            return None;
+        }
+        Some(
+            self.cookies
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// Convert into the tuple of URLs `get_download_info` used to return, for callers that
+    /// still expose that shape.
+    pub fn into_legacy(self) -> LegacyDownloadInfo {
+        let splits = self
+            .splits
+            .into_iter()
+            .map(|split| (split.name, split.url))
+            .collect();
+        let additional_files = self
+            .additional_files
+            .into_iter()
+            .map(|file| (Some(file.filename), file.url))
+            .collect();
+        (self.base.url, splits, additional_files)
+    }
+}
+
+/// The state produced by `Gpapi::login`, which can be persisted and later handed to
+/// `Gpapi::restore_session` in order to skip the checkin, device config upload, auth and toc
+/// round trips.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub device_codename: String,
+    /// The account the session was created for, empty for sessions stored before it was part of
+    /// the session, which are never restored.
+    #[serde(default)]
+    pub email: String,
+    pub locale: String,
+    /// The country the session logged in with, `None` for sessions stored before it was part of
+    /// the session, which keep the country set on the `Gpapi` restoring them.
+    #[serde(default)]
+    pub country: Option<String>,
+    pub gsf_id: i64,
+    pub device_checkin_consistency_token: Option<String>,
+    pub device_config_token: String,
+    pub auth_token: String,
+    /// Unix timestamp (in seconds) at which the auth token expires, if Google reported one.
+    #[serde(default)]
+    pub auth_token_expiry: Option<i64>,
+    pub dfe_cookie: Option<String>,
+}
+
+/// The current release of an app, see `Gpapi::latest_version`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    pub version_code: i32,
+    pub version_string: Option<String>,
+    /// When the release was published, formatted by Play for the locale, e.g. `Mar 4, 2025`.
+    pub updated_on: Option<String>,
+}
+
+impl VersionInfo {
+    /// Extract the release from a details response, `None` if it lacks a version code.
+    pub fn from_details(details: &DetailsResponse) -> Option<Self> {
+        let app_details = details
+            .item
+            .as_ref()?
+            .details
+            .as_ref()?
+            .app_details
+            .as_ref()?;
+
+        Some(VersionInfo {
+            version_code: app_details.version_code?,
+            version_string: app_details.version_string.clone(),
+            updated_on: app_details.info_updated_on.clone(),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Gpapi {
+    device_codename: String,
     locale: String,
+    country: String,
     timezone: String,
     device_properties: DeviceProperties,
     email: String,
     aas_token: Option<String>,
     auth_token: Option<String>,
+    auth_token_expiry: Option<i64>,
     device_config_token: Option<String>,
     device_checkin_consistency_token: Option<String>,
     tos_token: Option<String>,
     dfe_cookie: Option<String>,
     gsf_id: Option<i64>,
+    base_url: String,
     client: Box<reqwest::Client>,
 }
 
 impl Gpapi {
     /// Returns a Gpapi struct.
     ///
+    /// # Panics
+    ///
+    /// If `device_codename` does not name a bundled device profile, see `try_new`.
     pub fn new<S: Into<String>>(device_codename: S, email: S) -> Self {
+        Self::try_new(device_codename, email).expect("Invalid device codename")
+    }
+
+    /// Returns a Gpapi struct, or `UnknownDevice` if `device_codename` does not name a bundled
+    /// device profile.
+    pub fn try_new<S: Into<String>>(device_codename: S, email: S) -> Result<Self, GpapiError> {
+        let device_codename = device_codename.into();
+        let device = DeviceProperties::bundled(&device_codename)
+            .ok_or_else(|| GpapiError::new(GpapiErrorKind::UnknownDevice(device_codename)))?;
+        Ok(Self::with_device(device, email))
+    }
+
+    /// Returns a Gpapi struct presenting itself as `device`, e.g. one parsed at runtime with
+    /// `DeviceProperties::parse` instead of a bundled profile. Logging in fails with
+    /// `InvalidDeviceProperties` if the profile lacks a build property Play requires.
+    pub fn with_device<S: Into<String>>(device: DeviceProperties, email: S) -> Self {
         Gpapi {
             locale: String::from("en_US"),
+            country: String::from(consts::defaults::DEFAULT_COUNTRY_CODE),
             timezone: String::from("UTC"),
-            device_properties: bincode::deserialize::<HashMap<String, EncodedDeviceProperties>>(
-                DEVICES_ENCODED,
-            )
-            .unwrap()
-            .remove(&device_codename.into())
-            .expect("Invalid device codename")
-            .to_decoded(),
+            device_codename: device.codename.clone(),
+            device_properties: device,
             email: email.into(),
             aas_token: None,
             auth_token: None,
+            auth_token_expiry: None,
             device_config_token: None,
             device_checkin_consistency_token: None,
             tos_token: None,
             dfe_cookie: None,
             gsf_id: None,
+            base_url: String::from(consts::defaults::DEFAULT_BASE_URL),
             client: Box::new(reqwest::Client::new()),
         }
     }
 
-    /// Set the locale
+    /// Use a different Play Store host, e.g. a local mock server, instead of
+    /// `https://android.clients.google.com`.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The scheme, host and optional port requests are sent to, e.g.
+    ///   `http://127.0.0.1:8080`
+    pub fn with_base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Set the locale, e.g. `de_DE`, whose language is also reported when logging in
     pub fn set_locale<S: Into<String>>(&mut self, locale: S) {
         self.locale = locale.into();
     }
 
+    /// Set the country reported when logging in, as a two letter code, e.g. `de`
+    pub fn set_country<S: Into<String>>(&mut self, country: S) {
+        self.country = country.into();
+    }
+
     /// Set the time zone
     pub fn set_timezone<S: Into<String>>(&mut self, timezone: S) {
         self.timezone = timezone.into();
@@ -154,13 +613,18 @@ impl Gpapi {
     pub async fn request_aas_token<S: Into<String>>(
         &mut self,
         oauth_token: S,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> Result<(), GpapiError> {
         let oauth_token = oauth_token.into();
-        let auth_req = AuthRequest::new(&self.email, &oauth_token);
+        let auth_req = AuthRequest::new(
+            &self.email,
+            &oauth_token,
+            self.language(),
+            self.country.to_ascii_lowercase(),
+        );
         let mut resp = self.request_aas_token_helper(&auth_req).await?;
         self.aas_token = Some(
             resp.remove("token")
-                .ok_or(Box::new(GpapiError::new(GpapiErrorKind::Authentication)))?,
+                .ok_or(GpapiError::new(GpapiErrorKind::Authentication))?,
         );
         Ok(())
     }
@@ -168,7 +632,7 @@ impl Gpapi {
     async fn request_aas_token_helper(
         &self,
         auth_req: &AuthRequest,
-    ) -> Result<HashMap<String, String>, Box<dyn Error + Send + Sync>> {
+    ) -> Result<HashMap<String, String>, GpapiError> {
         let form_body = form_post(&auth_req.params);
 
         let mut headers = HashMap::new();
@@ -186,8 +650,9 @@ impl Gpapi {
             .execute_request_helper("auth", None, Some(&form_body.into_bytes()), headers, false)
             .await?;
 
-        let reply = parse_form_reply(&std::str::from_utf8(&body_bytes.to_vec()).unwrap());
-        Ok(reply)
+        let reply = std::str::from_utf8(&body_bytes)
+            .map_err(|_| GpapiError::new(GpapiErrorKind::InvalidResponse))?;
+        Ok(parse_form_reply(reply))
     }
 
     /// Get the aas token that has been previously set by either `request_aas_token` or
@@ -198,20 +663,68 @@ impl Gpapi {
 
     /// Log in to Google's Play Store API.  This is required for most other actions. The aas token
     /// has to be set via `request_aas_token` or `set_aas_token` first.
-    pub async fn login(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    pub async fn login(&mut self) -> Result<(), GpapiError> {
         self.checkin().await?;
-        if let Some(upload_device_config_token) = self.upload_device_config().await? {
-            self.device_config_token = Some(
-                upload_device_config_token
-                    .upload_device_config_token
-                    .unwrap(),
-            );
-            self.request_auth_token().await?;
-            self.toc().await?;
-            Ok(())
-        } else {
-            Err("No device config token".into())
+        let device_config_token = self
+            .upload_device_config()
+            .await?
+            .and_then(|response| response.upload_device_config_token)
+            .ok_or_else(|| {
+                GpapiError::new(GpapiErrorKind::IncompleteLogin(String::from(
+                    "device config token",
+                )))
+            })?;
+        self.device_config_token = Some(device_config_token);
+        self.request_auth_token().await?;
+        self.toc().await?;
+        Ok(())
+    }
+
+    /// Export the state of a logged in session, or `None` if `login` has not completed yet.
+    pub fn session(&self) -> Option<Session> {
+        Some(Session {
+            device_codename: self.device_codename.clone(),
+            email: self.email.clone(),
+            locale: self.locale.clone(),
+            country: Some(self.country.clone()),
+            gsf_id: self.gsf_id?,
+            device_checkin_consistency_token: self.device_checkin_consistency_token.clone(),
+            device_config_token: self.device_config_token.clone()?,
+            auth_token: self.auth_token.clone()?,
+            auth_token_expiry: self.auth_token_expiry,
+            dfe_cookie: self.dfe_cookie.clone(),
+        })
+    }
+
+    /// Restore a session previously exported with `session`, instead of calling `login`.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - A session exported from a `Gpapi` set up with the same device codename
+    pub fn restore_session(&mut self, session: Session) -> Result<(), GpapiError> {
+        if session.device_codename != self.device_codename {
+            return Err(GpapiError::new(GpapiErrorKind::SessionMismatch(format!(
+                "created for device '{}', not '{}'",
+                session.device_codename, self.device_codename
+            ))));
+        }
+        if session.email != self.email {
+            return Err(GpapiError::new(GpapiErrorKind::SessionMismatch(format!(
+                "created for account '{}', not '{}'",
+                session.email, self.email
+            ))));
+        }
+        self.locale = session.locale;
+        if let Some(country) = session.country {
+            self.country = country;
         }
+        self.gsf_id = Some(session.gsf_id);
+        self.device_checkin_consistency_token = session.device_checkin_consistency_token;
+        self.device_config_token = Some(session.device_config_token);
+        self.auth_token = Some(session.auth_token);
+        self.auth_token_expiry = session.auth_token_expiry;
+        self.dfe_cookie = session.dfe_cookie;
+        Ok(())
     }
 
     /// Retrieve the download URL(s) and names for a package, given a package ID and optional
@@ -221,25 +734,23 @@ impl Gpapi {
     ///
     /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
     /// * `version_code` - An optinal version code, given in i32.  If omitted, the latest version will
-    /// be used
+    ///   be used
     ///
     /// # Returns
     ///
-    /// * An Option<String> to the full APK download URL, followed by a Vec<(Option<String>,
-    /// Option<String>)> which corresponds to a list of download URLs and names for the split APK,
-    /// then followed by another Vec<(Option<String>, Option<String>)> which corresponds to the
-    /// download URLs and filenames for additional files.
+    /// * A `DownloadInfo` holding the base APK, the split APKs and the additional OBB files, each
+    ///   with its download URL and, where Play reports them, its size and hashes.
     pub async fn get_download_info<S: Into<String>>(
         &self,
         pkg_name: S,
         mut version_code: Option<i32>,
-    ) -> Result<DownloadInfo, Box<dyn Error + Send + Sync>> {
+    ) -> Result<DownloadInfo, GpapiError> {
         let pkg_name = pkg_name.into();
         if self.auth_token.is_none() {
-            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+            return Err(GpapiError::new(GpapiErrorKind::LoginRequired));
         }
         if version_code.is_none() {
-            version_code = Some(self.get_latest_version_for_pkg_name(&pkg_name).await?);
+            version_code = Some(self.latest_version(&pkg_name).await?.version_code);
         }
         let resp = {
             let version_code_string = version_code.unwrap().to_string();
@@ -263,7 +774,151 @@ impl Gpapi {
                 }
             }
         }
-        Err(Box::new(GpapiError::new(GpapiErrorKind::InvalidApp)))
+        Err(GpapiError::new(GpapiErrorKind::InvalidApp))
+    }
+
+    /// Download a package, given a package ID, optional version code, and filesystem path.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    /// * `version_code` - An optinal version code, given in i32.  If omitted, the latest version will
+    ///   be used
+    /// * `split_if_available` - A bool specifying whether split APKs should be downloaded as well
+    /// * `include_additional_files` - A bool specifying whether the main and patch OBB files
+    ///   should be downloaded as well
+    /// * `dst_path` - A path to download the files to, which has to be an existing directory
+    /// * `cb` - An optional callback, invoked every time a file has finished downloading
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn download<S: Into<String>>(
+        &self,
+        pkg_name: S,
+        version_code: Option<i32>,
+        split_if_available: bool,
+        include_additional_files: bool,
+        dst_path: &Path,
+        cb: Option<&dyn Fn()>,
+    ) -> Result<(), GpapiError> {
+        let pkg_name = pkg_name.into();
+        let download_info = self.get_download_info(&pkg_name, version_code).await?;
+        self.download_from_info(
+            &pkg_name,
+            download_info,
+            split_if_available,
+            include_additional_files,
+            dst_path,
+            cb,
+        )
+        .await
+    }
+
+    /// Download the files described by a `DownloadInfo` previously retrieved via
+    /// `get_download_info`.  The base APK is saved as `<pkg_name>.apk`, splits as
+    /// `<pkg_name>.<split_name>.apk` and additional files under their OBB filenames. The
+    /// download cookies are sent with every request.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    /// * `download_info` - The download info returned by `get_download_info`
+    /// * `split_if_available` - A bool specifying whether split APKs should be downloaded as well
+    /// * `include_additional_files` - A bool specifying whether the main and patch OBB files
+    ///   should be downloaded as well
+    /// * `dst_path` - A path to download the files to, which has to be an existing directory
+    /// * `cb` - An optional callback, invoked every time a file has finished downloading
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn download_from_info<S: Into<String>>(
+        &self,
+        pkg_name: S,
+        download_info: DownloadInfo,
+        split_if_available: bool,
+        include_additional_files: bool,
+        dst_path: &Path,
+        cb: Option<&dyn Fn()>,
+    ) -> Result<(), GpapiError> {
+        let pkg_name = pkg_name.into();
+        if !dst_path.is_dir() {
+            return Err(GpapiError::new(GpapiErrorKind::DirectoryMissing));
+        }
+
+        let cookie = download_info.cookie_header();
+        let download_url = download_info
+            .base
+            .url
+            .ok_or(GpapiError::new(GpapiErrorKind::InvalidApp))?;
+
+        let mut files = vec![(dst_path.join(format!("{}.apk", pkg_name)), download_url)];
+        if split_if_available {
+            for split in download_info.splits {
+                if let (Some(name), Some(url)) = (split.name, split.url) {
+                    files.push((dst_path.join(format!("{}.{}.apk", pkg_name, name)), url));
+                }
+            }
+        }
+        if include_additional_files {
+            for additional_file in download_info.additional_files {
+                if let Some(url) = additional_file.url {
+                    files.push((dst_path.join(additional_file.filename), url));
+                }
+            }
+        }
+
+        // Refuse to clobber anything before the first byte is written, so a failed download
+        // never leaves a mix of old and new files behind.
+        if files.iter().any(|(file_path, _)| file_path.exists()) {
+            return Err(GpapiError::new(GpapiErrorKind::FileExists));
+        }
+
+        let result =
+            futures::future::try_join_all(files.iter().map(|(file_path, url)| {
+                self.download_from_url(url, cookie.as_deref(), file_path, cb)
+            }))
+            .await;
+        // None of the files existed before, so whatever is there now is a partial download
+        // which would otherwise make retrying fail with `FileExists`.
+        if let Err(err) = result {
+            for (file_path, _) in &files {
+                let _ = std::fs::remove_file(file_path);
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn download_from_url(
+        &self,
+        url: &str,
+        cookie: Option<&str>,
+        file_path: &Path,
+        cb: Option<&dyn Fn()>,
+    ) -> Result<(), GpapiError> {
+        use std::fs::OpenOptions;
+        use std::io::{ErrorKind as IOErrorKind, Write};
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(file_path)
+            .map_err(|err| match err.kind() {
+                IOErrorKind::AlreadyExists => GpapiError::new(GpapiErrorKind::FileExists),
+                IOErrorKind::PermissionDenied => GpapiError::new(GpapiErrorKind::PermissionDenied),
+                _ => GpapiError::from(err),
+            })?;
+
+        let mut req = (*self.client).get(url);
+        if let Some(cookie) = cookie {
+            req = req.header(reqwest::header::COOKIE, cookie);
+        }
+        let mut res = req.send().await?.error_for_status()?;
+        while let Some(chunk) = res.chunk().await? {
+            file.write_all(&chunk)?;
+        }
+
+        if let Some(cb) = cb {
+            cb();
+        }
+        Ok(())
     }
 
     async fn delivery<S: Into<String>>(
@@ -271,14 +926,14 @@ impl Gpapi {
         pkg_name: S,
         mut version_code: Option<i32>,
         delivery_token: S,
-    ) -> Result<DownloadInfo, Box<dyn Error + Send + Sync>> {
+    ) -> Result<DownloadInfo, GpapiError> {
         let pkg_name = pkg_name.into();
         let delivery_token = delivery_token.into();
         if self.auth_token.is_none() {
-            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+            return Err(GpapiError::new(GpapiErrorKind::LoginRequired));
         }
         if version_code.is_none() {
-            version_code = Some(self.get_latest_version_for_pkg_name(&pkg_name).await?);
+            version_code = Some(self.latest_version(&pkg_name).await?.version_code);
         }
         let resp = {
             let version_code_string = version_code.unwrap().to_string();
@@ -292,52 +947,98 @@ impl Gpapi {
         };
         if let Some(payload) = resp.payload {
             if let Some(delivery_response) = payload.delivery_response {
+                let message = match delivery_response.status {
+                    None | Some(1) => None,
+                    Some(2) | Some(9) => Some("The app is not supported by this device"),
+                    Some(3) => Some("The app has not been purchased by this account"),
+                    Some(7) => Some("The app has been removed from Google Play"),
+                    Some(_) => Some("The app cannot be delivered to this account"),
+                };
+                if let Some(message) = message {
+                    return Err(GpapiError::new(GpapiErrorKind::Server(message.to_string())));
+                }
                 if let Some(app_delivery_data) = delivery_response.app_delivery_data {
-                    let mut splits = Vec::new();
-                    for app_split_delivery_data in app_delivery_data.split_delivery_data {
-                        splits.push((
-                            app_split_delivery_data.name,
-                            app_split_delivery_data.download_url,
-                        ));
-                    }
-                    let mut additional_files: Vec<(Option<String>, Option<String>)> = Vec::new();
+                    let base = ApkFile {
+                        url: app_delivery_data.download_url,
+                        size: app_delivery_data.download_size,
+                        sha1: app_delivery_data.sha1,
+                        sha256: app_delivery_data.sha256,
+                        compressed_url: app_delivery_data.compressed_download_url,
+                        compressed_size: app_delivery_data.compressed_size,
+                    };
+                    let splits = app_delivery_data
+                        .split_delivery_data
+                        .into_iter()
+                        .map(|split| SplitFile {
+                            name: split.name,
+                            url: split.download_url,
+                            size: split.download_size,
+                            sha1: split.sha1,
+                            sha256: split.sha256,
+                            compressed_url: split.compressed_download_url,
+                            compressed_size: split.compressed_size,
+                        })
+                        .collect();
+                    let mut additional_files = Vec::new();
                     for additional_file in app_delivery_data.additional_file {
                         if let Some(file_type) = additional_file.file_type {
                             if let Some(version_code) = additional_file.version_code {
-                                let main_patch = match file_type {
-                                    0 => "main",
-                                    _ => "patch",
+                                let file_type = match file_type {
+                                    0 => AdditionalFileType::Main,
+                                    _ => AdditionalFileType::Patch,
                                 };
-                                let filename =
-                                    format!("{}.{}.{}.obb", main_patch, version_code, pkg_name);
-                                additional_files
-                                    .push((Some(filename), additional_file.download_url));
+                                additional_files.push(AdditionalFile {
+                                    file_type,
+                                    version_code,
+                                    filename: format!(
+                                        "{}.{}.{}.obb",
+                                        file_type, version_code, pkg_name
+                                    ),
+                                    url: additional_file.download_url,
+                                    size: additional_file.size,
+                                    sha1: additional_file.sha1,
+                                    compressed_url: additional_file.compressed_download_url,
+                                    compressed_size: additional_file.compressed_size,
+                                });
                             }
                         }
                     }
-                    return Ok((app_delivery_data.download_url, splits, additional_files));
+                    let cookies = app_delivery_data
+                        .download_auth_cookie
+                        .into_iter()
+                        .filter_map(|cookie| {
+                            Some(DownloadCookie {
+                                name: cookie.name?,
+                                value: cookie.value?,
+                            })
+                        })
+                        .collect();
+                    return Ok(DownloadInfo {
+                        base,
+                        splits,
+                        additional_files,
+                        cookies,
+                    });
                 }
             }
         }
-        Err(Box::new(GpapiError::new(GpapiErrorKind::InvalidApp)))
+        Err(GpapiError::new(GpapiErrorKind::InvalidApp))
     }
 
-    async fn get_latest_version_for_pkg_name(
+    /// The current release of an app for this account and device.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    pub async fn latest_version<S: Into<String>>(
         &self,
-        pkg_name: &str,
-    ) -> Result<i32, Box<dyn Error + Send + Sync>> {
-        if let Some(details) = self.details(pkg_name).await? {
-            if let Some(item) = details.item {
-                if let Some(details) = item.details {
-                    if let Some(app_details) = details.app_details {
-                        if let Some(version_code) = app_details.version_code {
-                            return Ok(version_code);
-                        }
-                    }
-                }
-            }
-        }
-        Err(Box::new(GpapiError::new(GpapiErrorKind::InvalidApp)))
+        pkg_name: S,
+    ) -> Result<VersionInfo, GpapiError> {
+        self.details(pkg_name)
+            .await?
+            .as_ref()
+            .and_then(VersionInfo::from_details)
+            .ok_or_else(|| GpapiError::new(GpapiErrorKind::InvalidApp))
     }
 
     /// Play Store package detail request (provides more detail than bulk requests).
@@ -348,9 +1049,9 @@ impl Gpapi {
     pub async fn details<S: Into<String>>(
         &self,
         pkg_name: S,
-    ) -> Result<Option<DetailsResponse>, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Option<DetailsResponse>, GpapiError> {
         if self.auth_token.is_none() {
-            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+            return Err(GpapiError::new(GpapiErrorKind::LoginRequired));
         }
         let mut form_params = HashMap::new();
         form_params.insert("doc", pkg_name.into());
@@ -376,9 +1077,9 @@ impl Gpapi {
     pub async fn bulk_details(
         &self,
         pkg_names: &[&str],
-    ) -> Result<Option<BulkDetailsResponse>, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Option<BulkDetailsResponse>, GpapiError> {
         if self.auth_token.is_none() {
-            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+            return Err(GpapiError::new(GpapiErrorKind::LoginRequired));
         }
         let mut req = BulkDetailsRequest::default();
         req.doc_id = pkg_names.into_iter().cloned().map(String::from).collect();
@@ -401,17 +1102,93 @@ impl Gpapi {
         }
     }
 
-    async fn checkin(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    /// Join the testing program of an app, i.e. its beta or alpha track.
+    ///
+    /// Returns whether Play confirmed the subscription. It may take a while until details and
+    /// downloads reflect the new track.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    pub async fn join_testing_program<S: Into<String>>(
+        &self,
+        pkg_name: S,
+    ) -> Result<bool, GpapiError> {
+        self.testing_program(pkg_name.into(), true).await
+    }
+
+    /// Leave the testing program of an app, going back to its production track.
+    ///
+    /// Returns whether Play confirmed the unsubscription.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    pub async fn leave_testing_program<S: Into<String>>(
+        &self,
+        pkg_name: S,
+    ) -> Result<bool, GpapiError> {
+        self.testing_program(pkg_name.into(), false).await
+    }
+
+    /// The account's enrollment in the testing program of an app, as reported by its details.
+    /// `None` if the app does not offer a testing program to this account.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    pub async fn testing_program_info<S: Into<String>>(
+        &self,
+        pkg_name: S,
+    ) -> Result<Option<TestingProgramInfo>, GpapiError> {
+        let details = self
+            .details(pkg_name)
+            .await?
+            .ok_or_else(|| GpapiError::new(GpapiErrorKind::InvalidApp))?;
+
+        Ok(details
+            .item
+            .and_then(|item| item.details)
+            .and_then(|details| details.app_details)
+            .and_then(|app_details| app_details.testing_program_info))
+    }
+
+    async fn testing_program(&self, pkg_name: String, subscribe: bool) -> Result<bool, GpapiError> {
+        if self.auth_token.is_none() {
+            return Err(GpapiError::new(GpapiErrorKind::LoginRequired));
+        }
+        let req = TestingProgramRequest {
+            package_name: Some(pkg_name),
+            subscribe: Some(subscribe),
+        };
+        let bytes = req.encode_to_vec();
+
+        let mut headers = self.get_default_headers()?;
+        headers.insert("content-type", String::from("application/x-protobuf"));
+
+        let resp = self
+            .execute_request("apps/testingProgram", None, Some(&bytes), headers)
+            .await?;
+        let details = resp
+            .payload
+            .and_then(|payload| payload.testing_program_response)
+            .and_then(|response| response.result)
+            .and_then(|result| result.details)
+            .ok_or_else(|| GpapiError::new(GpapiErrorKind::InvalidResponse))?;
+
+        if subscribe {
+            Ok(details.subscribed.unwrap_or(false))
+        } else {
+            Ok(details.unsubscribed.unwrap_or(false))
+        }
+    }
+
+    async fn checkin(&mut self) -> Result<(), GpapiError> {
         let checkin = self.device_properties.android_checkin.clone();
 
-        let build_device = checkin
-            .build
-            .as_ref()
-            .unwrap()
-            .device
-            .as_ref()
-            .unwrap()
-            .clone();
+        let build_device = self
+            .device_properties
+            .build_property("Build.DEVICE", |build| build.device.clone())?;
 
         let mut req = AndroidCheckinRequest::default();
         req.id = Some(0);
@@ -425,12 +1202,7 @@ impl Gpapi {
         bytes.reserve(req.encoded_len());
         req.encode(&mut bytes).unwrap();
 
-        let build_id = self
-            .device_properties
-            .extra_info
-            .get("Build.ID")
-            .unwrap()
-            .clone();
+        let build_id = self.device_properties.extra("Build.ID").to_string();
         let mut headers = HashMap::new();
         self.append_auth_headers(&mut headers, build_device, build_id);
 
@@ -444,9 +1216,9 @@ impl Gpapi {
         &self,
         msg: &[u8],
         mut auth_headers: HashMap<&str, String>,
-    ) -> Result<AndroidCheckinResponse, Box<dyn Error + Send + Sync>> {
+    ) -> Result<AndroidCheckinResponse, GpapiError> {
         auth_headers.insert("content-type", String::from("application/x-protobuf"));
-        auth_headers.insert("host", String::from("android.clients.google.com"));
+        auth_headers.insert("host", self.host()?);
         let bytes = self
             .execute_request_helper("checkin", None, Some(msg), auth_headers, false)
             .await?;
@@ -454,7 +1226,16 @@ impl Gpapi {
         Ok(resp)
     }
 
-    fn get_default_headers(&self) -> Result<HashMap<&str, String>, Box<dyn Error + Send + Sync>> {
+    fn host(&self) -> Result<String, GpapiError> {
+        let url = parse_url(&self.base_url)?;
+        let host = url.host_str().ok_or("Base URL has no host")?;
+        Ok(match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        })
+    }
+
+    fn get_default_headers(&self) -> Result<HashMap<&str, String>, GpapiError> {
         let mut headers = HashMap::new();
         self.append_default_headers(&mut headers)?;
         Ok(headers)
@@ -463,39 +1244,25 @@ impl Gpapi {
     fn append_default_headers(
         &self,
         headers: &mut HashMap<&str, String>,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> Result<(), GpapiError> {
         if let Some(auth_token) = &self.auth_token {
             headers.insert("Authorization", format!("Bearer {}", auth_token.clone()));
         }
 
-        let build = self
-            .device_properties
-            .android_checkin
-            .clone()
-            .build
-            .unwrap();
-        let device_configuration = self.device_properties.device_configuration.clone();
-
+        let device = &self.device_properties;
         let build_configuration = BuildConfiguration::new(
-            self.device_properties
-                .extra_info
-                .get("Vending.versionString")
-                .unwrap(),
-            self.device_properties
-                .extra_info
-                .get("Vending.version")
-                .unwrap(),
-            &build.sdk_version.as_ref().unwrap().to_string(),
-            &build.device.as_ref().unwrap(),
-            &build.product.as_ref().unwrap(),
-            &build.build_product.as_ref().unwrap(),
-            self.device_properties
-                .extra_info
-                .get("Build.VERSION.RELEASE")
-                .unwrap(),
-            &build.model.as_ref().unwrap(),
-            self.device_properties.extra_info.get("Build.ID").unwrap(),
-            &device_configuration.native_platform.join(";"),
+            device.extra("Vending.versionString"),
+            device.extra("Vending.version"),
+            &device
+                .build_property("Build.VERSION.SDK_INT", |build| build.sdk_version)?
+                .to_string(),
+            &device.build_property("Build.DEVICE", |build| build.device.clone())?,
+            &device.build_property("Build.HARDWARE", |build| build.product.clone())?,
+            &device.build_property("Build.PRODUCT", |build| build.build_product.clone())?,
+            device.extra("Build.VERSION.RELEASE"),
+            &device.build_property("Build.MODEL", |build| build.model.clone())?,
+            device.extra("Build.ID"),
+            &device.device_configuration.native_platform.join(";"),
         );
 
         headers.insert("user-agent", build_configuration.user_agent());
@@ -560,41 +1327,46 @@ impl Gpapi {
         }
     }
 
-    fn append_default_auth_params(&self, params: &mut HashMap<&str, String>) {
+    /// The language part of the locale, e.g. `de` for `de_DE`.
+    fn language(&self) -> String {
+        match self.locale.split(['_', '-']).next() {
+            Some(language) if !language.is_empty() => language.to_ascii_lowercase(),
+            _ => String::from(consts::defaults::DEFAULT_LANGUAGE),
+        }
+    }
+
+    fn append_default_auth_params(
+        &self,
+        params: &mut HashMap<&str, String>,
+    ) -> Result<(), GpapiError> {
         if let Some(gsf_id) = self.gsf_id {
             params.insert("androidId", format!("{:x}", gsf_id));
         }
 
-        let build = self
-            .device_properties
-            .android_checkin
-            .clone()
-            .build
-            .unwrap();
+        let device = &self.device_properties;
         params.insert(
             "sdk_version",
-            build.sdk_version.as_ref().unwrap().to_string(),
+            device
+                .build_property("Build.VERSION.SDK_INT", |build| build.sdk_version)?
+                .to_string(),
         );
         params.insert("Email", self.email.clone());
         params.insert(
             "google_play_services_version",
-            build.google_services.as_ref().unwrap().to_string(),
-        );
-        params.insert(
-            "device_country",
-            String::from(consts::defaults::DEFAULT_COUNTRY_CODE).to_ascii_lowercase(),
-        );
-        params.insert(
-            "lang",
-            String::from(consts::defaults::DEFAULT_LANGUAGE).to_ascii_lowercase(),
+            device
+                .build_property("GSF.version", |build| build.google_services)?
+                .to_string(),
         );
+        params.insert("device_country", self.country.to_ascii_lowercase());
+        params.insert("lang", self.language());
         params.insert(
             "callerSig",
             String::from(consts::defaults::DEFAULT_CALLER_SIG),
         );
+        Ok(())
     }
 
-    fn append_auth_params(&self, params: &mut HashMap<&str, String>) {
+    fn append_auth_params(&self, params: &mut HashMap<&str, String>) -> Result<(), GpapiError> {
         params.insert("app", String::from("com.android.vending"));
         params.insert(
             "client_sig",
@@ -604,16 +1376,19 @@ impl Gpapi {
             "callerPkg",
             String::from(consts::defaults::DEFAULT_ANDROID_VENDING),
         );
-        params.insert("Token", self.aas_token.as_ref().unwrap().clone());
+        let aas_token = self
+            .aas_token
+            .clone()
+            .ok_or_else(|| GpapiError::new(GpapiErrorKind::Authentication))?;
+        params.insert("Token", aas_token);
         params.insert("oauth2_foreground", String::from("1"));
         params.insert("token_request_options", String::from("CAA4AVAB"));
         params.insert("check_email", String::from("1"));
         params.insert("system_partition", String::from("1"));
+        Ok(())
     }
 
-    async fn upload_device_config(
-        &self,
-    ) -> Result<Option<UploadDeviceConfigResponse>, Box<dyn Error + Send + Sync>> {
+    async fn upload_device_config(&self) -> Result<Option<UploadDeviceConfigResponse>, GpapiError> {
         let mut req = UploadDeviceConfigRequest::default();
         req.device_configuration = Some(self.device_properties.device_configuration.clone());
         let mut bytes = Vec::new();
@@ -633,11 +1408,11 @@ impl Gpapi {
         }
     }
 
-    async fn request_auth_token(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    async fn request_auth_token(&mut self) -> Result<(), GpapiError> {
         let form_params = {
             let mut params = HashMap::new();
-            self.append_default_auth_params(&mut params);
-            self.append_auth_params(&mut params);
+            self.append_default_auth_params(&mut params)?;
+            self.append_auth_params(&mut params)?;
             params.insert(
                 "service",
                 String::from("oauth2:https://www.googleapis.com/auth/googleplay"),
@@ -649,21 +1424,8 @@ impl Gpapi {
             let mut headers = HashMap::new();
             let build_device = self
                 .device_properties
-                .android_checkin
-                .clone()
-                .build
-                .as_ref()
-                .unwrap()
-                .device
-                .as_ref()
-                .unwrap()
-                .clone();
-            let build_id = self
-                .device_properties
-                .extra_info
-                .get("Build.ID")
-                .unwrap()
-                .clone();
+                .build_property("Build.DEVICE", |build| build.device.clone())?;
+            let build_id = self.device_properties.extra("Build.ID").to_string();
             self.append_auth_headers(&mut headers, build_device, build_id);
             headers.insert("content-length", String::from("0"));
             headers
@@ -673,36 +1435,47 @@ impl Gpapi {
             .execute_request_helper("auth", Some(form_params), Some(&[]), headers, false)
             .await?;
 
-        let reply = parse_form_reply(&std::str::from_utf8(&bytes.to_vec()).unwrap());
-        self.auth_token = reply.get("auth").map(|a| a.clone());
+        let reply = std::str::from_utf8(&bytes)
+            .map_err(|_| GpapiError::new(GpapiErrorKind::InvalidResponse))?;
+        let reply = parse_form_reply(reply);
+        self.auth_token = Some(
+            reply
+                .get("auth")
+                .cloned()
+                .ok_or_else(|| GpapiError::new(GpapiErrorKind::Authentication))?,
+        );
+        self.auth_token_expiry = reply.get("expiry").and_then(|e| e.parse().ok());
         Ok(())
     }
 
-    async fn toc(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    async fn toc(&mut self) -> Result<(), GpapiError> {
         let resp = self
             .execute_request("toc", None, None, self.get_default_headers()?)
             .await?;
         let toc_response = resp
             .payload
-            .ok_or(Box::new(GpapiError::from("Invalid payload.")))?
-            .toc_response
-            .ok_or(Box::new(GpapiError::from("Invalid toc response.")))?;
+            .and_then(|payload| payload.toc_response)
+            .ok_or_else(|| {
+                GpapiError::new(GpapiErrorKind::IncompleteLogin(String::from(
+                    "toc response",
+                )))
+            })?;
         if toc_response.tos_token.is_some() || toc_response.tos_content.is_some() {
             self.tos_token = toc_response.tos_token.clone();
-            return Err(Box::new(GpapiError::new(GpapiErrorKind::TermsOfService)));
+            return Err(GpapiError::new(GpapiErrorKind::TermsOfService));
         }
         if let Some(cookie) = toc_response.cookie {
             self.dfe_cookie = Some(cookie.clone());
             Ok(())
         } else {
-            Err("No DFE cookie found.".into())
+            Err(GpapiError::new(GpapiErrorKind::IncompleteLogin(
+                String::from("DFE cookie"),
+            )))
         }
     }
 
     /// Accept the play store terms of service.
-    pub async fn accept_tos(
-        &mut self,
-    ) -> Result<Option<AcceptTosResponse>, Box<dyn Error + Send + Sync>> {
+    pub async fn accept_tos(&mut self) -> Result<Option<AcceptTosResponse>, GpapiError> {
         if let Some(tos_token) = &self.tos_token {
             let form_body = {
                 let mut params = HashMap::new();
@@ -725,7 +1498,7 @@ impl Gpapi {
                 Ok(None)
             }
         } else {
-            Err("ToS token must be set by `toc` call first.".into())
+            Err(GpapiError::new(GpapiErrorKind::NoTermsOfService))
         }
     }
 
@@ -738,11 +1511,16 @@ impl Gpapi {
         query: Option<HashMap<&str, String>>,
         msg: Option<&[u8]>,
         headers: HashMap<&str, String>,
-    ) -> Result<ResponseWrapper, Box<dyn Error + Send + Sync>> {
+    ) -> Result<ResponseWrapper, GpapiError> {
         let bytes = self
             .execute_request_helper(endpoint, query, msg, headers, true)
             .await?;
         let resp = ResponseWrapper::decode(&mut Cursor::new(bytes))?;
+        if resp.payload.is_none() {
+            if let Some(message) = server_error_message(&resp) {
+                return Err(GpapiError::new(GpapiErrorKind::Server(message)));
+            }
+        }
         Ok(resp)
     }
 
@@ -803,19 +1581,11 @@ impl Gpapi {
         msg: Option<&[u8]>,
         headers: HashMap<&str, String>,
         fdfe: bool,
-    ) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Bytes, GpapiError> {
         let mut url = if fdfe {
-            Url::parse(&format!(
-                "{}/fdfe/{}",
-                consts::defaults::DEFAULT_BASE_URL,
-                endpoint
-            ))?
+            parse_url(&format!("{}/fdfe/{}", self.base_url, endpoint))?
         } else {
-            Url::parse(&format!(
-                "{}/{}",
-                consts::defaults::DEFAULT_BASE_URL,
-                endpoint
-            ))?
+            parse_url(&format!("{}/{}", self.base_url, endpoint))?
         };
 
         if let Some(query) = query {
@@ -850,10 +1620,49 @@ impl Gpapi {
             }
         };
 
+        let status = res.status();
+        if !status.is_success() {
+            // Error responses of the fdfe endpoints usually still carry a `ResponseWrapper`,
+            // whose server commands explain what went wrong.
+            let message = if fdfe {
+                let body = res.bytes().await.unwrap_or_default();
+                ResponseWrapper::decode(&mut Cursor::new(body))
+                    .ok()
+                    .and_then(|resp| server_error_message(&resp))
+            } else {
+                None
+            };
+
+            // An expired or revoked auth token is answered with 401 on the fdfe endpoints, while
+            // the auth endpoint itself answers 401 or 403 when the aas token is no longer valid.
+            let kind = match (status.as_u16(), message) {
+                (401, _) if fdfe => GpapiErrorKind::LoginRequired,
+                (401 | 403, _) if !fdfe => GpapiErrorKind::Authentication,
+                (429, _) => GpapiErrorKind::RateLimited,
+                (_, Some(message)) => GpapiErrorKind::Server(message),
+                (status, None) => GpapiErrorKind::HttpStatus(status),
+            };
+            return Err(GpapiError::new(kind));
+        }
+
         Ok(res.bytes().await?)
     }
 }
 
+/// The error message Play asks to be displayed to the user, e.g. when an app is not available in
+/// the country of the account.
+fn server_error_message(resp: &ResponseWrapper) -> Option<String> {
+    resp.commands
+        .as_ref()?
+        .display_error_message
+        .clone()
+        .filter(|message| !message.is_empty())
+}
+
+fn parse_url(url: &str) -> Result<Url, GpapiError> {
+    Url::parse(url).map_err(|err| GpapiError::new(GpapiErrorKind::Other(Box::new(err))))
+}
+
 fn parse_form_reply(data: &str) -> HashMap<String, String> {
     let mut form_resp = HashMap::new();
     let lines: Vec<&str> = data.split_terminator('\n').collect();
@@ -873,7 +1682,9 @@ struct AuthRequest {
 }
 
 impl AuthRequest {
-    fn new(email: &str, oauth_token: &str) -> Self {
+    /// An auth request for the account `email`, made with the `Gpapi`'s language and country,
+    /// as `append_default_auth_params` does for logging in.
+    fn new(email: &str, oauth_token: &str, language: String, country: String) -> Self {
         let mut auth_request = Self::default();
         auth_request
             .params
@@ -881,6 +1692,10 @@ impl AuthRequest {
         auth_request
             .params
             .insert(String::from("Token"), String::from(oauth_token));
+        auth_request.params.insert(String::from("lang"), language);
+        auth_request
+            .params
+            .insert(String::from("device_country"), country);
         auth_request
     }
 }
@@ -1009,6 +1824,156 @@ mod tests {
         assert_eq!(expected_reply, parsed_form_reply);
     }
 
+    const DEVICE: &str = "\
+Build.DEVICE=lynx
+Build.HARDWARE=lynx
+Build.PRODUCT=lynx
+Build.MODEL=Pixel 7a
+Build.VERSION.SDK_INT=34
+GSF.version=241518037
+Build.ID=UQ1A.240205.002
+Screen.Density=420
+Platforms=arm64-v8a,armeabi-v7a
+SharedLibraries=android.test.runner
+Features=android.hardware.wifi,android.hardware.vulkan.version=4206592
+Locales=en,de
+GL.Extensions=GL_OES_EGL_image
+";
+
+    #[test]
+    fn parse_device_properties() {
+        let device = DeviceProperties::parse(DEVICE).unwrap();
+        assert_eq!(device.codename, "lynx");
+        let build = device.android_checkin.build.as_ref().unwrap();
+        assert_eq!(build.model.as_deref(), Some("Pixel 7a"));
+        assert_eq!(build.sdk_version, Some(34));
+        let config = &device.device_configuration;
+        assert_eq!(config.screen_density, Some(420));
+        assert_eq!(config.native_platform, vec!["arm64-v8a", "armeabi-v7a"]);
+        assert_eq!(config.device_feature[0].value, Some(0));
+        assert_eq!(
+            config.device_feature[1].name.as_deref(),
+            Some("android.hardware.vulkan.version")
+        );
+        assert_eq!(config.device_feature[1].value, Some(4206592));
+        assert_eq!(
+            config.system_available_feature,
+            vec!["android.hardware.wifi", "android.hardware.vulkan.version"]
+        );
+        assert!(device
+            .validate()
+            .contains(&String::from("missing 'GL.Version'")));
+        assert_eq!(device.extra_info["Build.ID"], "UQ1A.240205.002");
+
+        let sections = format!(
+            "[gplayapi_px_7a.properties]\n{}\n[gplayapi_other.properties]\n{}",
+            DEVICE, DEVICE
+        );
+        let devices = DeviceProperties::parse_all(&sections).unwrap();
+        assert!(devices.contains_key("px_7a") && devices.contains_key("other"));
+        assert!(DeviceProperties::parse(&sections).is_err());
+        let sections = format!(
+            "[gplayapi_px_7a.properties]\n{}\n[gplayapi_other.properties]\nBuild.DEVICE=lynx\n",
+            DEVICE
+        );
+        let err = DeviceProperties::parse_all(&sections).unwrap_err();
+        assert!(
+            matches!(err.kind(), GpapiErrorKind::InvalidDeviceProperties(e) if e.starts_with("device 'other': missing"))
+        );
+
+        let err = DeviceProperties::parse("Build.DEVICE=lynx\n").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            GpapiErrorKind::InvalidDeviceProperties(_)
+        ));
+        let incomplete =
+            DeviceProperties::parse(&DEVICE.replace("GL.Extensions", "GL.Other")).unwrap();
+        assert!(incomplete.device_configuration.gl_extension.is_empty());
+        assert!(incomplete
+            .validate()
+            .contains(&String::from("missing 'GL.Extensions'")));
+        assert!(DeviceProperties::bundled("px_7a")
+            .unwrap()
+            .validate()
+            .is_empty());
+
+        let err = DeviceProperties::parse(&DEVICE.replace("=4206592", "=new")).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            GpapiErrorKind::InvalidDeviceProperties(_)
+        ));
+
+        let summary = device.summary();
+        assert_eq!(summary.manufacturer, None);
+        assert_eq!(summary.abis, vec!["arm64-v8a", "armeabi-v7a"]);
+        assert_eq!(summary.locales, vec!["en", "de"]);
+
+        let api = Gpapi::with_device(device, "test@example.com");
+        assert_eq!(api.device_codename, "lynx");
+    }
+
+    #[test]
+    fn list_devices() {
+        let devices = devices();
+        let px_7a = devices
+            .iter()
+            .find(|device| device.codename == "px_7a")
+            .unwrap();
+        assert!(px_7a.sdk_version.is_some());
+        assert!(!px_7a.abis.is_empty());
+        assert!(devices.iter().all(|device| has_device(&device.codename)));
+        assert!(devices
+            .windows(2)
+            .all(|pair| pair[0].codename < pair[1].codename));
+    }
+
+    #[test]
+    fn build_device() {
+        let base = DeviceProperties::bundled("px_7a").unwrap();
+        let device = DevicePropertiesBuilder::bundled("px_7a")
+            .unwrap()
+            .codename("px_7a_x86_64")
+            .abis(["x86_64", "x86"])
+            .sdk_version(35)
+            .screen_density(240)
+            .gl_extensions(["GL_OES_EGL_image"])
+            .features([
+                ("android.hardware.wifi", 0),
+                ("android.hardware.vulkan.version", 4206592),
+            ])
+            .locales(["de_DE"])
+            .build();
+
+        let summary = device.summary();
+        assert_eq!(summary.codename, "px_7a_x86_64");
+        assert_eq!(summary.abis, vec!["x86_64", "x86"]);
+        assert_eq!(summary.sdk_version, Some(35));
+        assert_eq!(summary.screen_density, Some(240));
+        assert_eq!(summary.locales, vec!["de_DE"]);
+        assert_eq!(summary.model, base.summary().model);
+        let config = &device.device_configuration;
+        assert_eq!(config.gl_extension, vec!["GL_OES_EGL_image"]);
+        assert_eq!(
+            config.system_available_feature,
+            vec!["android.hardware.wifi", "android.hardware.vulkan.version"]
+        );
+        assert_eq!(
+            config.device_feature[1].name.as_deref(),
+            Some("android.hardware.vulkan.version")
+        );
+        assert_eq!(config.device_feature[1].value, Some(4206592));
+        assert_eq!(config.screen_width, base.device_configuration.screen_width);
+
+        assert!(DevicePropertiesBuilder::bundled("no_such_device").is_err());
+    }
+
+    #[test]
+    fn try_new_unknown_device() {
+        assert!(Gpapi::try_new("px_7a", "test@example.com").is_ok());
+        let err = Gpapi::try_new("no_such_device", "test@example.com").unwrap_err();
+        assert!(matches!(err.kind(), GpapiErrorKind::UnknownDevice(_)));
+    }
+
     mod gpapi {
         use std::env;
 
@@ -1079,4 +2044,647 @@ mod tests {
             bdr.include_child_docs = Some(true);
         }
     }
+
+    mod offline {
+        use super::*;
+        use crate::mock_server::*;
+        use googleplay_protobuf::{
+            AndroidAppDeliveryData, AppDetails, AppFileMetadata, BulkDetailsEntry, BuyResponse,
+            DeliveryResponse, DocumentDetails, HttpCookie, Item, Payload, ServerCommands,
+            SplitDeliveryData, TestingProgramDetails, TestingProgramResponse, TestingProgramResult,
+            TocResponse,
+        };
+
+        const PKG_NAME: &str = "com.example.app";
+
+        async fn logged_in(server: &MockServer) -> Gpapi {
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            api.set_aas_token("mock-aas-token");
+            api.login().await.unwrap();
+            api
+        }
+
+        fn app_item(pkg_name: &str, version_code: i32) -> Item {
+            Item {
+                id: Some(pkg_name.to_string()),
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        package_name: Some(pkg_name.to_string()),
+                        version_code: Some(version_code),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        }
+
+        fn mock_details(server: &MockServer, version_code: i32) {
+            server.mock_payload(
+                "/fdfe/details",
+                Payload {
+                    details_response: Some(DetailsResponse {
+                        item: Some(app_item(PKG_NAME, version_code)),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+        }
+
+        fn mock_delivery(server: &MockServer) {
+            server.mock_payload(
+                "/fdfe/purchase",
+                Payload {
+                    buy_response: Some(BuyResponse {
+                        encoded_delivery_token: Some(String::from("mock-delivery-token")),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+            server.mock_payload(
+                "/fdfe/delivery",
+                Payload {
+                    delivery_response: Some(DeliveryResponse {
+                        status: Some(1),
+                        app_delivery_data: Some(AndroidAppDeliveryData {
+                            download_url: Some(format!("{}/files/base.apk", server.base_url())),
+                            download_size: Some(4),
+                            sha1: Some(String::from("base-sha1")),
+                            split_delivery_data: vec![SplitDeliveryData {
+                                name: Some(String::from("config.arm64_v8a")),
+                                sha256: Some(String::from("split-sha256")),
+                                compressed_size: Some(3),
+                                compressed_download_url: Some(format!(
+                                    "{}/files/config.arm64_v8a.apk.gz",
+                                    server.base_url()
+                                )),
+                                download_url: Some(format!(
+                                    "{}/files/config.arm64_v8a.apk",
+                                    server.base_url()
+                                )),
+                                ..Default::default()
+                            }],
+                            additional_file: vec![AppFileMetadata {
+                                file_type: Some(0),
+                                version_code: Some(42),
+                                download_url: Some(format!("{}/files/main.obb", server.base_url())),
+                                size: Some(3),
+                                sha1: Some(String::from("obb-sha1")),
+                                ..Default::default()
+                            }],
+                            download_auth_cookie: vec![HttpCookie {
+                                name: Some(String::from("MarketDA")),
+                                value: Some(String::from("mock-cookie")),
+                            }],
+                            ..Default::default()
+                        }),
+                    }),
+                    ..Default::default()
+                },
+            );
+            server.mock("/files/base.apk", 200, b"base".to_vec());
+            server.mock("/files/config.arm64_v8a.apk", 200, b"split".to_vec());
+            server.mock("/files/main.obb", 200, b"obb".to_vec());
+        }
+
+        fn temp_dir(name: &str) -> std::path::PathBuf {
+            let dir = std::env::temp_dir().join(format!("gpapi-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        #[tokio::test]
+        async fn test_login() {
+            let server = MockServer::start().await;
+            let api = logged_in(&server).await;
+
+            assert_eq!(api.gsf_id, Some(MOCK_GSF_ID as i64));
+            assert_eq!(
+                api.device_checkin_consistency_token.as_deref(),
+                Some(MOCK_CHECKIN_TOKEN)
+            );
+            assert_eq!(
+                api.device_config_token.as_deref(),
+                Some(MOCK_DEVICE_CONFIG_TOKEN)
+            );
+            assert_eq!(api.auth_token.as_deref(), Some(MOCK_AUTH_TOKEN));
+            assert_eq!(api.dfe_cookie.as_deref(), Some(MOCK_DFE_COOKIE));
+
+            let checkin = server.requests_to("/checkin");
+            assert_eq!(checkin[0].method, "POST");
+            assert_eq!(
+                checkin[0].headers.get("host").map(String::as_str),
+                Some(server.base_url().trim_start_matches("http://"))
+            );
+        }
+
+        #[test]
+        fn test_has_device() {
+            assert!(has_device("px_7a"));
+            assert!(!has_device("not_a_device"));
+        }
+
+        #[tokio::test]
+        async fn test_country() {
+            let server = MockServer::start().await;
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            api.set_aas_token("mock-aas-token");
+            api.set_country("DE");
+            api.set_locale("de_AT");
+            api.login().await.unwrap();
+
+            let auth = &server.requests_to("/auth")[0];
+            assert_eq!(
+                auth.query.get("device_country").map(String::as_str),
+                Some("de")
+            );
+            assert_eq!(auth.query.get("lang").map(String::as_str), Some("de"));
+        }
+
+        #[tokio::test]
+        async fn test_request_aas_token_locale() {
+            let server = MockServer::start().await;
+            server.mock("/auth", 200, b"Token=mock-aas-token\n".to_vec());
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            api.set_country("AT");
+            api.set_locale("de_AT");
+            api.request_aas_token("mock-oauth-token").await.unwrap();
+            assert_eq!(api.get_aas_token(), Some("mock-aas-token"));
+
+            let auth = &server.requests_to("/auth")[0];
+            let body = parse_form_reply(&String::from_utf8_lossy(&auth.body).replace('&', "\n"));
+            assert_eq!(body.get("lang").map(String::as_str), Some("de"));
+            assert_eq!(body.get("device_country").map(String::as_str), Some("at"));
+            assert_eq!(
+                body.get("token").map(String::as_str),
+                Some("mock-oauth-token")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_login_errors() {
+            let server = MockServer::start().await;
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            let err = api.login().await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::Authentication));
+            let err = api.accept_tos().await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::NoTermsOfService));
+
+            api.set_aas_token("mock-aas-token");
+            server.mock("/auth", 200, vec![0xff, 0xfe]);
+            let err = api.login().await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::InvalidResponse));
+            server.mock("/auth", 200, b"Expiry=0\n".to_vec());
+            let err = api.login().await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::Authentication));
+
+            let server = MockServer::start().await;
+            server.mock_payload(
+                "/fdfe/toc",
+                Payload {
+                    toc_response: Some(TocResponse::default()),
+                    ..Default::default()
+                },
+            );
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            api.set_aas_token("mock-aas-token");
+            let err = api.login().await.unwrap_err();
+            assert!(
+                matches!(err.kind(), GpapiErrorKind::IncompleteLogin(missing) if missing == "DFE cookie")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_login_incomplete_device() {
+            let clear_build_product = |build: &mut AndroidBuildProto| build.build_product = None;
+            let clear_gsf_version = |build: &mut AndroidBuildProto| build.google_services = None;
+            for clear in [clear_build_product, clear_gsf_version] {
+                let server = MockServer::start().await;
+                let mut device = DeviceProperties::bundled("px_7a").unwrap();
+                clear(device.android_checkin.build.as_mut().unwrap());
+                let mut api =
+                    Gpapi::with_device(device, "test@example.com").with_base_url(server.base_url());
+                api.set_aas_token("mock-aas-token");
+
+                let err = api.login().await.unwrap_err();
+                assert!(matches!(
+                    err.kind(),
+                    GpapiErrorKind::InvalidDeviceProperties(_)
+                ));
+            }
+        }
+
+        #[tokio::test]
+        async fn test_restore_session() {
+            let server = MockServer::start().await;
+            mock_details(&server, 1337);
+            let mut api = logged_in(&server).await;
+            api.set_country("AT");
+            let session = api.session().unwrap();
+            assert_eq!(session.auth_token, MOCK_AUTH_TOKEN);
+            assert_eq!(session.country.as_deref(), Some("AT"));
+            assert_eq!(session.auth_token_expiry, Some(MOCK_AUTH_TOKEN_EXPIRY));
+
+            let encoded = bincode::serialize(&session).unwrap();
+            let decoded: Session = bincode::deserialize(&encoded).unwrap();
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            assert!(api.session().is_none());
+            api.restore_session(decoded).unwrap();
+            assert_eq!(api.session(), Some(session.clone()));
+
+            assert!(api.details(PKG_NAME).await.unwrap().is_some());
+            assert_eq!(server.requests_to("/checkin").len(), 1);
+
+            let mut api = Gpapi::new("px_7a", "test@example.com");
+            api.set_country("DE");
+            api.restore_session(Session {
+                country: None,
+                ..session.clone()
+            })
+            .unwrap();
+            assert_eq!(api.session().unwrap().country.as_deref(), Some("DE"));
+
+            let mut api = Gpapi::new("px_7a", "other@example.com");
+            let err = api.restore_session(session.clone()).unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::SessionMismatch(_)));
+
+            let mut api = Gpapi::new("ad_g3_pro", "test@example.com");
+            let err = api.restore_session(session).unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::SessionMismatch(_)));
+        }
+
+        #[tokio::test]
+        async fn test_details() {
+            let server = MockServer::start().await;
+            mock_details(&server, 1337);
+            let api = logged_in(&server).await;
+
+            let details = api.details(PKG_NAME).await.unwrap().unwrap();
+            assert_eq!(details.item.unwrap().id.as_deref(), Some(PKG_NAME));
+
+            let req = &server.requests_to("/fdfe/details")[0];
+            assert_eq!(req.method, "GET");
+            assert_eq!(req.query.get("doc").map(String::as_str), Some(PKG_NAME));
+            assert_eq!(
+                req.headers.get("authorization"),
+                Some(&format!("Bearer {}", MOCK_AUTH_TOKEN))
+            );
+            assert_eq!(
+                req.headers.get("x-dfe-cookie").map(String::as_str),
+                Some(MOCK_DFE_COOKIE)
+            );
+        }
+
+        #[tokio::test]
+        async fn test_latest_version() {
+            let server = MockServer::start().await;
+            let mut item = app_item(PKG_NAME, 1337);
+            if let Some(app_details) = item
+                .details
+                .as_mut()
+                .and_then(|details| details.app_details.as_mut())
+            {
+                app_details.version_string = Some(String::from("1.3.37"));
+                app_details.info_updated_on = Some(String::from("Mar 4, 2025"));
+            }
+            server.mock_payload(
+                "/fdfe/details",
+                Payload {
+                    details_response: Some(DetailsResponse {
+                        item: Some(item),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+            let api = logged_in(&server).await;
+
+            assert_eq!(
+                api.latest_version(PKG_NAME).await.unwrap(),
+                VersionInfo {
+                    version_code: 1337,
+                    version_string: Some(String::from("1.3.37")),
+                    updated_on: Some(String::from("Mar 4, 2025")),
+                }
+            );
+
+            server.mock_payload("/fdfe/details", Payload::default());
+            let err = api.latest_version(PKG_NAME).await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::InvalidApp));
+        }
+
+        #[tokio::test]
+        async fn test_expired_auth_token() {
+            let server = MockServer::start().await;
+            server.mock("/fdfe/details", 401, Vec::new());
+            let api = logged_in(&server).await;
+
+            let err = api.details(PKG_NAME).await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::LoginRequired));
+
+            server.mock("/auth", 401, b"Error=BadAuthentication\n".to_vec());
+            let mut api = Gpapi::new("px_7a", "test@example.com").with_base_url(server.base_url());
+            api.set_aas_token("revoked-aas-token");
+            let err = api.login().await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::Authentication));
+        }
+
+        #[tokio::test]
+        async fn test_http_errors() {
+            let server = MockServer::start().await;
+            let api = logged_in(&server).await;
+
+            server.mock("/fdfe/details", 429, Vec::new());
+            let err = api.details(PKG_NAME).await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::RateLimited));
+
+            server.mock("/fdfe/details", 500, Vec::new());
+            let err = api.details(PKG_NAME).await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::HttpStatus(500)));
+
+            server.mock("/fdfe/details", 200, vec![0xff, 0xff, 0xff]);
+            let err = api.details(PKG_NAME).await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::Decode(_)));
+        }
+
+        #[tokio::test]
+        async fn test_server_errors() {
+            let server = MockServer::start().await;
+            let api = logged_in(&server).await;
+            let unavailable = ResponseWrapper {
+                commands: Some(ServerCommands {
+                    display_error_message: Some(String::from(
+                        "This item isn't available in your country.",
+                    )),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+
+            server.mock_wrapper("/fdfe/details", 200, unavailable.clone());
+            let err = api.details(PKG_NAME).await.unwrap_err();
+            assert!(
+                matches!(err.kind(), GpapiErrorKind::Server(message) if message.contains("country"))
+            );
+
+            server.mock_wrapper("/fdfe/details", 404, unavailable);
+            let err = api.details(PKG_NAME).await.unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::Server(_)));
+
+            mock_delivery(&server);
+            server.mock_payload(
+                "/fdfe/delivery",
+                Payload {
+                    delivery_response: Some(DeliveryResponse {
+                        status: Some(3),
+                        app_delivery_data: None,
+                    }),
+                    ..Default::default()
+                },
+            );
+            let err = api.get_download_info(PKG_NAME, Some(42)).await.unwrap_err();
+            assert!(
+                matches!(err.kind(), GpapiErrorKind::Server(message) if message.contains("purchased"))
+            );
+        }
+
+        #[tokio::test]
+        async fn test_testing_program() {
+            let server = MockServer::start().await;
+            server.mock_payload(
+                "/fdfe/apps/testingProgram",
+                Payload {
+                    testing_program_response: Some(TestingProgramResponse {
+                        result: Some(TestingProgramResult {
+                            details: Some(TestingProgramDetails {
+                                subscribed: Some(true),
+                                ..Default::default()
+                            }),
+                        }),
+                    }),
+                    ..Default::default()
+                },
+            );
+            let api = logged_in(&server).await;
+
+            assert!(api.join_testing_program(PKG_NAME).await.unwrap());
+            // Play answers a leave it did not carry out without `unsubscribed`.
+            assert!(!api.leave_testing_program(PKG_NAME).await.unwrap());
+            server.mock_payload(
+                "/fdfe/apps/testingProgram",
+                Payload {
+                    testing_program_response: Some(TestingProgramResponse {
+                        result: Some(TestingProgramResult {
+                            details: Some(TestingProgramDetails {
+                                unsubscribed: Some(true),
+                                ..Default::default()
+                            }),
+                        }),
+                    }),
+                    ..Default::default()
+                },
+            );
+            assert!(api.leave_testing_program(PKG_NAME).await.unwrap());
+            assert!(!api.join_testing_program(PKG_NAME).await.unwrap());
+
+            let requests = server.requests_to("/fdfe/apps/testingProgram");
+            let join = TestingProgramRequest::decode(requests[0].body.as_slice()).unwrap();
+            assert_eq!(join.package_name.as_deref(), Some(PKG_NAME));
+            assert_eq!(join.subscribe, Some(true));
+            let leave = TestingProgramRequest::decode(requests[1].body.as_slice()).unwrap();
+            assert_eq!(leave.subscribe, Some(false));
+
+            let mut item = app_item(PKG_NAME, 42);
+            if let Some(app_details) = item
+                .details
+                .as_mut()
+                .and_then(|details| details.app_details.as_mut())
+            {
+                app_details.testing_program_info = Some(TestingProgramInfo {
+                    subscribed: Some(true),
+                    ..Default::default()
+                });
+            }
+            server.mock_payload(
+                "/fdfe/details",
+                Payload {
+                    details_response: Some(DetailsResponse {
+                        item: Some(item),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+            let info = api.testing_program_info(PKG_NAME).await.unwrap().unwrap();
+            assert_eq!(info.subscribed, Some(true));
+        }
+
+        #[tokio::test]
+        async fn test_bulk_details() {
+            let server = MockServer::start().await;
+            server.mock_payload(
+                "/fdfe/bulkDetails",
+                Payload {
+                    bulk_details_response: Some(BulkDetailsResponse {
+                        entry: vec![
+                            BulkDetailsEntry {
+                                item: Some(app_item("com.example.one", 1)),
+                            },
+                            BulkDetailsEntry {
+                                item: Some(app_item("com.example.two", 2)),
+                            },
+                        ],
+                    }),
+                    ..Default::default()
+                },
+            );
+            let api = logged_in(&server).await;
+
+            let resp = api
+                .bulk_details(&["com.example.one", "com.example.two"])
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(resp.entry.len(), 2);
+
+            let req = &server.requests_to("/fdfe/bulkDetails")[0];
+            let sent = BulkDetailsRequest::decode(&mut Cursor::new(&req.body)).unwrap();
+            assert_eq!(sent.doc_id, vec!["com.example.one", "com.example.two"]);
+        }
+
+        #[tokio::test]
+        async fn test_get_download_info() {
+            let server = MockServer::start().await;
+            mock_details(&server, 42);
+            mock_delivery(&server);
+            let api = logged_in(&server).await;
+
+            let download_info = api.get_download_info(PKG_NAME, None).await.unwrap();
+            assert!(download_info
+                .base
+                .url
+                .as_deref()
+                .unwrap()
+                .ends_with("/files/base.apk"));
+            assert_eq!(download_info.base.size, Some(4));
+            assert_eq!(download_info.base.sha1.as_deref(), Some("base-sha1"));
+            assert_eq!(
+                download_info.splits[0].name.as_deref(),
+                Some("config.arm64_v8a")
+            );
+            assert_eq!(
+                download_info.splits[0].sha256.as_deref(),
+                Some("split-sha256")
+            );
+            assert_eq!(download_info.base.compressed_url, None);
+            assert!(download_info.splits[0]
+                .compressed_url
+                .as_deref()
+                .unwrap()
+                .ends_with("/files/config.arm64_v8a.apk.gz"));
+            assert_eq!(download_info.splits[0].compressed_size, Some(3));
+            let main = &download_info.additional_files[0];
+            assert_eq!(main.file_type, AdditionalFileType::Main);
+            assert_eq!(main.filename, "main.42.com.example.app.obb");
+            assert_eq!(main.size, Some(3));
+            assert_eq!(main.sha1.as_deref(), Some("obb-sha1"));
+            assert_eq!(
+                download_info.cookie_header().as_deref(),
+                Some("MarketDA=mock-cookie")
+            );
+
+            let (base, splits, additional_files) = download_info.into_legacy();
+            assert!(base.unwrap().ends_with("/files/base.apk"));
+            assert_eq!(splits[0].0.as_deref(), Some("config.arm64_v8a"));
+            assert_eq!(
+                additional_files[0].0.as_deref(),
+                Some("main.42.com.example.app.obb")
+            );
+
+            let purchase = &server.requests_to("/fdfe/purchase")[0];
+            assert_eq!(purchase.query.get("vc").map(String::as_str), Some("42"));
+            let delivery = &server.requests_to("/fdfe/delivery")[0];
+            assert_eq!(
+                delivery.query.get("dtok").map(String::as_str),
+                Some("mock-delivery-token")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_download() {
+            let server = MockServer::start().await;
+            mock_details(&server, 42);
+            mock_delivery(&server);
+            let api = logged_in(&server).await;
+            let dir = temp_dir("download");
+
+            api.download(PKG_NAME, Some(42), true, true, &dir, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                std::fs::read(dir.join("com.example.app.apk")).unwrap(),
+                b"base"
+            );
+            assert_eq!(
+                std::fs::read(dir.join("com.example.app.config.arm64_v8a.apk")).unwrap(),
+                b"split"
+            );
+            assert_eq!(
+                std::fs::read(dir.join("main.42.com.example.app.obb")).unwrap(),
+                b"obb"
+            );
+            for file in [
+                "/files/base.apk",
+                "/files/config.arm64_v8a.apk",
+                "/files/main.obb",
+            ] {
+                let req = &server.requests_to(file)[0];
+                assert_eq!(
+                    req.headers.get("cookie").map(String::as_str),
+                    Some("MarketDA=mock-cookie")
+                );
+            }
+
+            let err = api
+                .download(PKG_NAME, Some(42), true, true, &dir, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::FileExists));
+
+            let err = api
+                .download(PKG_NAME, Some(42), false, false, &dir.join("missing"), None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err.kind(), GpapiErrorKind::DirectoryMissing));
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_download_failure() {
+            let server = MockServer::start().await;
+            mock_details(&server, 42);
+            mock_delivery(&server);
+            server.mock("/files/config.arm64_v8a.apk", 500, Vec::new());
+            let api = logged_in(&server).await;
+            let dir = temp_dir("download-failure");
+
+            assert!(api
+                .download(PKG_NAME, Some(42), true, true, &dir, None)
+                .await
+                .is_err());
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+            server.mock("/files/config.arm64_v8a.apk", 200, b"split".to_vec());
+            api.download(PKG_NAME, Some(42), true, true, &dir, None)
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
 }
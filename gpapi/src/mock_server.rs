@@ -0,0 +1,212 @@
+//! A minimal Play Store stand-in used by the offline tests.
+//!
+//! The server speaks just enough HTTP/1.1 for `reqwest` and answers every path with a canned
+//! response.  The login handshake (`checkin`, `uploadDeviceConfig`, `auth` and `toc`) is served
+//! out of the box, everything else has to be registered with `MockServer::mock` or
+//! `MockServer::mock_payload`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use googleplay_protobuf::{
+    AndroidCheckinResponse, Payload, ResponseWrapper, TocResponse, UploadDeviceConfigResponse,
+};
+use prost::Message;
+use reqwest::Url;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const MOCK_GSF_ID: u64 = 0x3a1f_2b4c_5d6e_7f80;
+pub const MOCK_CHECKIN_TOKEN: &str = "mock-checkin-consistency-token";
+pub const MOCK_DEVICE_CONFIG_TOKEN: &str = "mock-device-config-token";
+pub const MOCK_AUTH_TOKEN: &str = "mock-auth-token";
+pub const MOCK_AUTH_TOKEN_EXPIRY: i64 = 1_700_000_000;
+pub const MOCK_DFE_COOKIE: &str = "mock-dfe-cookie";
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    routes: HashMap<String, (u16, Vec<u8>)>,
+    requests: Vec<RecordedRequest>,
+}
+
+pub struct MockServer {
+    base_url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    /// Start a server on a random local port, preloaded with a successful login handshake.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, server_state.clone()));
+            }
+        });
+
+        let server = MockServer { base_url, state };
+        server.mock(
+            "/checkin",
+            200,
+            AndroidCheckinResponse {
+                android_id: Some(MOCK_GSF_ID),
+                device_checkin_consistency_token: Some(MOCK_CHECKIN_TOKEN.to_string()),
+                ..Default::default()
+            }
+            .encode_to_vec(),
+        );
+        server.mock(
+            "/auth",
+            200,
+            format!(
+                "SID=BAD_COOKIE\nAuth={}\nExpiry={}\n",
+                MOCK_AUTH_TOKEN, MOCK_AUTH_TOKEN_EXPIRY
+            )
+            .into_bytes(),
+        );
+        server.mock_payload(
+            "/fdfe/uploadDeviceConfig",
+            Payload {
+                upload_device_config_response: Some(UploadDeviceConfigResponse {
+                    upload_device_config_token: Some(MOCK_DEVICE_CONFIG_TOKEN.to_string()),
+                }),
+                ..Default::default()
+            },
+        );
+        server.mock_payload(
+            "/fdfe/toc",
+            Payload {
+                toc_response: Some(TocResponse {
+                    cookie: Some(MOCK_DFE_COOKIE.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        server
+    }
+
+    /// The base URL to hand to `Gpapi::with_base_url`.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Serve `body` with the given status for every request to `path`.
+    pub fn mock(&self, path: &str, status: u16, body: Vec<u8>) {
+        self.state
+            .lock()
+            .unwrap()
+            .routes
+            .insert(path.to_string(), (status, body));
+    }
+
+    /// Serve a `ResponseWrapper` carrying `payload` for every request to `path`.
+    pub fn mock_payload(&self, path: &str, payload: Payload) {
+        self.mock_wrapper(
+            path,
+            200,
+            ResponseWrapper {
+                payload: Some(payload),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Serve an arbitrary `ResponseWrapper` with the given status for every request to `path`.
+    pub fn mock_wrapper(&self, path: &str, status: u16, wrapper: ResponseWrapper) {
+        self.mock(path, status, wrapper.encode_to_vec());
+    }
+
+    /// All requests received so far, in order of arrival.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// The requests received so far for a single path.
+    pub fn requests_to(&self, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|req| req.path == path)
+            .collect()
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut buf = Vec::new();
+    let head_len = loop {
+        let mut chunk = [0u8; 4096];
+        let n = match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_len]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default().to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|len| len.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = buf[head_len..].to_vec();
+    while body.len() < content_length {
+        let mut chunk = [0u8; 4096];
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => body.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    let url = Url::parse(&format!("http://localhost{}", target)).unwrap();
+    let path = url.path().to_string();
+    let query = url.query_pairs().into_owned().collect();
+
+    let (status, response_body) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(RecordedRequest {
+            method,
+            path: path.clone(),
+            query,
+            headers,
+            body,
+        });
+        state
+            .routes
+            .get(&path)
+            .cloned()
+            .unwrap_or((404, Vec::new()))
+    };
+
+    let response_head = format!(
+        "HTTP/1.1 {} Mock\r\ncontent-type: application/x-protobuf\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        status,
+        response_body.len()
+    );
+    let _ = stream.write_all(response_head.as_bytes()).await;
+    let _ = stream.write_all(&response_body).await;
+    let _ = stream.shutdown().await;
+}
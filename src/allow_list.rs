@@ -0,0 +1,121 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use worker::{console_log, Env};
+
+use crate::google_play_client::Channel;
+
+/// The KV namespace and key checked first, so the list can be edited without redeploying.
+const ALLOW_LIST_KV_BINDING: &str = "TRACKS";
+const ALLOW_LIST_KV_KEY: &str = "allow_list";
+/// The worker var used when the KV key is missing.
+const ALLOW_LIST_VAR: &str = "CHANNEL_ALLOW_LIST";
+
+/// Packages that are always served on a channel, without detecting its track first.
+///
+/// Both sources hold a JSON object mapping channel names to package names, where a `*` matches
+/// any run of characters, e.g. `{"beta": ["com.discord*"], "alpha": ["com.discord"]}`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AllowList(HashMap<String, Vec<String>>);
+
+impl AllowList {
+    pub async fn load(env: &Env) -> Self {
+        if let Ok(kv) = env.kv(ALLOW_LIST_KV_BINDING) {
+            match kv.get(ALLOW_LIST_KV_KEY).json::<AllowList>().await {
+                Ok(Some(allow_list)) => return allow_list,
+                Ok(None) => {}
+                Err(e) => console_log!("Failed to load allow list from KV: {:?}", e),
+            }
+        }
+
+        match env.var(ALLOW_LIST_VAR) {
+            Ok(var) => serde_json::from_str(&var.to_string()).unwrap_or_else(|e| {
+                console_log!("Ignoring malformed {}: {}", ALLOW_LIST_VAR, e);
+                AllowList::default()
+            }),
+            Err(_) => AllowList::default(),
+        }
+    }
+
+    pub fn allows(&self, channel: &Channel, package_name: &str) -> bool {
+        self.0.get(&channel.to_string()).is_some_and(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, package_name))
+        })
+    }
+}
+
+fn matches_pattern(pattern: &str, package_name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match package_name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.split_last() {
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(pos) => rest = &rest[pos + part.len()..],
+                    None => return false,
+                }
+            }
+            last
+        }
+        None => return rest.is_empty(),
+    };
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_pattern() {
+        assert!(matches_pattern("com.discord", "com.discord"));
+        assert!(!matches_pattern("com.discord", "com.discord.beta"));
+        assert!(!matches_pattern("com.discord", "org.com.discord"));
+    }
+
+    #[test]
+    fn trailing_wildcard() {
+        assert!(matches_pattern("com.discord*", "com.discord"));
+        assert!(matches_pattern("com.discord*", "com.discord.beta"));
+        assert!(!matches_pattern("com.discord*", "com.disc"));
+    }
+
+    #[test]
+    fn leading_wildcard() {
+        assert!(matches_pattern("*.discord", "com.discord"));
+        assert!(!matches_pattern("*.discord", "com.discord.beta"));
+        assert!(matches_pattern("*", "com.discord"));
+    }
+
+    #[test]
+    fn middle_wildcard() {
+        assert!(matches_pattern("com.*.android", "com.instagram.android"));
+        assert!(matches_pattern(
+            "com.*.*.android",
+            "com.facebook.lite.android"
+        ));
+        assert!(!matches_pattern("com.*.android", "com.instagram.lite"));
+        assert!(!matches_pattern("com.*.android", "com.android"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+
+    #[test]
+    fn allows_per_channel() {
+        let allow_list: AllowList =
+            serde_json::from_str(r#"{"beta": ["com.discord*"], "alpha": ["com.discord"]}"#)
+                .unwrap();
+        assert!(allow_list.allows(&Channel::new("Beta"), "com.discord.beta"));
+        assert!(allow_list.allows(&Channel::new("alpha"), "com.discord"));
+        assert!(!allow_list.allows(&Channel::new("alpha"), "com.discord.beta"));
+        assert!(!allow_list.allows(&Channel::stable(), "com.discord"));
+    }
+}
@@ -1,156 +1,797 @@
-use gpapi::DownloadInfo;
+use googleplay_protobuf::{
+    AppDetails, BulkDetailsEntry, DetailsResponse, Item, TestingProgramInfo,
+};
+use gpapi::{DownloadInfo, Session, VersionInfo};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use worker::{console_log, Env};
+use worker::{console_log, Date, Env};
 
+use crate::allow_list::AllowList;
+use crate::error::{Error, ErrorKind, ErrorSummary};
 use crate::google_play_client::{Channel, GooglePlayClient};
+use crate::profiles::{load_profiles, Profile};
+use crate::track_cache::TrackCache;
+
+const SESSIONS_KV_BINDING: &str = "SESSIONS";
+/// How long a session is kept when Google does not report when its auth token expires.
+const DEFAULT_SESSION_TTL_SECS: u64 = 60 * 60;
+/// Sessions are evicted this long before their auth token actually expires.
+const SESSION_EXPIRY_MARGIN_SECS: u64 = 5 * 60;
+/// The shortest expiration KV accepts.
+const MIN_KV_TTL_SECS: u64 = 60;
+
+/// The channels a bulk lookup found a package on, and those it could not check it on.
+#[derive(Debug, Default)]
+pub struct BulkDetails {
+    pub entries: HashMap<Channel, BulkDetailsEntry>,
+    pub channel_errors: HashMap<Channel, ErrorSummary>,
+}
+
+/// The outcome of a bulk lookup for a single package.
+pub type BulkDetailsResult = Result<BulkDetails, Error>;
+
+/// Clients are kept per channel, device and locale, as the same account can log in as several
+/// devices and Play localizes its responses for the locale a client logged in with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    channel: Channel,
+    device: String,
+    /// gpapi's default locale and country are used if unset.
+    locale: Option<String>,
+    country: Option<String>,
+}
+
+impl std::fmt::Display for ClientKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}", self.channel, self.device)?;
+        if let Some(locale) = &self.locale {
+            write!(f, ", {}", locale)?;
+        }
+        if let Some(country) = &self.country {
+            write!(f, ", {}", country)?;
+        }
+        write!(f, ")")
+    }
+}
 
 pub struct ClientRegistry {
-    clients: HashMap<Channel, GooglePlayClient>,
-    initialized: HashMap<Channel, bool>,
+    clients: HashMap<ClientKey, GooglePlayClient>,
+    initialized: HashMap<ClientKey, bool>,
+    tracks: TrackCache,
+    allow_list: Option<AllowList>,
+    profiles: Option<Vec<Profile>>,
+    device: Option<String>,
+    locale: Option<String>,
+    country: Option<String>,
     env: Env,
 }
 
+/// Clients without a locale or country keep the key sessions were stored under before those
+/// were part of it. The account is not part of the key, as gpapi refuses to restore a session
+/// created for another email, after which it is replaced by a fresh login.
+fn session_key(key: &ClientKey) -> String {
+    match (&key.locale, &key.country) {
+        (None, None) => format!("session:{}:{}", key.channel, key.device),
+        (locale, country) => format!(
+            "session:{}:{}:{}:{}",
+            key.channel,
+            key.device,
+            locale.as_deref().unwrap_or_default(),
+            country.as_deref().unwrap_or_default()
+        ),
+    }
+}
+
+/// Tracks depend on the device and country a channel logs in as, but not on the locale.
+fn tracks_key(key: &ClientKey) -> String {
+    format!(
+        "tracks:{}:{}:{}",
+        key.channel,
+        key.device,
+        key.country.as_deref().unwrap_or_default()
+    )
+}
+
+fn is_alpha(s: &str, len: std::ops::RangeInclusive<usize>) -> bool {
+    len.contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// Normalizes a locale given as e.g. `de`, `de-AT` or `de_at` to Android's `de_AT`.
+fn normalize_locale(locale: &str) -> Option<String> {
+    let mut parts = locale.split(['_', '-']);
+    let language = parts.next().filter(|language| is_alpha(language, 2..=3))?;
+    match (parts.next(), parts.next()) {
+        (None, _) => Some(language.to_ascii_lowercase()),
+        (Some(region), None) if is_alpha(region, 2..=2) => Some(format!(
+            "{}_{}",
+            language.to_ascii_lowercase(),
+            region.to_ascii_uppercase()
+        )),
+        _ => None,
+    }
+}
+
+/// The locale and country requested as `hl` and `gl`, see `ClientRegistry::set_locale`.
+fn parse_locale(
+    hl: Option<&str>,
+    gl: Option<&str>,
+) -> Result<(Option<String>, Option<String>), Error> {
+    let country = match gl {
+        Some(gl) if is_alpha(gl, 2..=2) => Some(gl.to_ascii_lowercase()),
+        Some(gl) => {
+            return Err(Error::new(ErrorKind::InvalidRequest(format!(
+                "malformed country '{}'",
+                gl
+            ))))
+        }
+        None => None,
+    };
+    let locale = match hl {
+        Some(hl) => match normalize_locale(hl) {
+            Some(locale) if locale.contains('_') => Some(locale),
+            Some(language) => Some(match &country {
+                Some(country) => format!("{}_{}", language, country.to_ascii_uppercase()),
+                None => language,
+            }),
+            None => {
+                return Err(Error::new(ErrorKind::InvalidRequest(format!(
+                    "malformed locale '{}'",
+                    hl
+                ))))
+            }
+        },
+        None => None,
+    };
+
+    Ok((locale, country))
+}
+
+fn app_details(item: &Item) -> Option<&AppDetails> {
+    item.details.as_ref()?.app_details.as_ref()
+}
+
+fn is_enrolled(item: &Item) -> bool {
+    app_details(item)
+        .and_then(|details| details.testing_program_info.as_ref())
+        .and_then(|info| info.subscribed)
+        .unwrap_or(false)
+}
+
+/// Whether a channel account is served its own build of an app, either because it is enrolled in
+/// the app's testing program or because it sees a newer version than the stable account.
+fn has_track(item: &Item, stable: Option<&Item>) -> bool {
+    let version_code = app_details(item).and_then(|details| details.version_code);
+    let stable_version_code = stable
+        .and_then(app_details)
+        .and_then(|details| details.version_code);
+
+    is_enrolled(item) || matches!((version_code, stable_version_code), (Some(v), Some(s)) if v > s)
+}
+
+fn channel_unavailable(package_name: &str, channel: &Channel) -> Error {
+    Error::new(ErrorKind::ChannelUnavailable(package_name.to_string()))
+        .with_channel(channel.clone())
+}
+
 impl ClientRegistry {
     pub fn new(env: Env) -> Self {
         Self {
             clients: HashMap::new(),
             initialized: HashMap::new(),
+            tracks: TrackCache::new(env.clone()),
+            allow_list: None,
+            profiles: None,
+            device: None,
+            locale: None,
+            country: None,
             env,
         }
     }
 
-    pub async fn get_client(&mut self, channel: Channel) -> Result<&GooglePlayClient, String> {
-        if !self.clients.contains_key(&channel) {
-            let device_name = self.env.var("DEVICE_NAME").unwrap().to_string();
-
-            let (email, aas_token) = match channel {
-                Channel::Stable => (
-                    self.env.var("STABLE_EMAIL").unwrap().to_string(),
-                    self.env.var("STABLE_AAS_TOKEN").unwrap().to_string(),
-                ),
-                Channel::Beta => (
-                    self.env.var("BETA_EMAIL").unwrap().to_string(),
-                    self.env.var("BETA_AAS_TOKEN").unwrap().to_string(),
-                ),
-                Channel::Alpha => (
-                    self.env.var("ALPHA_EMAIL").unwrap().to_string(),
-                    self.env.var("ALPHA_AAS_TOKEN").unwrap().to_string(),
-                ),
+    fn var(&self, name: &str) -> Result<String, Error> {
+        self.env
+            .var(name)
+            .map(|var| var.to_string())
+            .map_err(|_| Error::new(ErrorKind::Config(format!("'{}' is not set", name))))
+    }
+
+    fn profiles(&mut self) -> Result<&[Profile], Error> {
+        if self.profiles.is_none() {
+            self.profiles = Some(load_profiles(&self.env)?);
+        }
+
+        Ok(self.profiles.as_deref().unwrap_or_default())
+    }
+
+    /// Resolves a channel name from a request, failing with `InvalidChannel` unless a profile of
+    /// that name is configured.
+    pub fn channel(&mut self, name: &str) -> Result<Channel, Error> {
+        let channel = Channel::new(name);
+        if self.profiles()?.iter().any(|p| p.channel() == channel) {
+            Ok(channel)
+        } else {
+            Err(Error::new(ErrorKind::InvalidChannel(name.to_string())))
+        }
+    }
+
+    /// Every configured channel except stable, in configuration order.
+    fn track_channels(&mut self) -> Result<Vec<Channel>, Error> {
+        Ok(self
+            .profiles()?
+            .iter()
+            .map(Profile::channel)
+            .filter(|channel| !channel.is_stable())
+            .collect())
+    }
+
+    fn profile(&mut self, channel: &Channel) -> Result<Profile, Error> {
+        self.profiles()?
+            .iter()
+            .find(|p| &p.channel() == channel)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidChannel(channel.to_string())))
+    }
+
+    /// Makes every channel log in as `device` rather than its profile's device, failing with
+    /// `InvalidRequest` if gpapi does not know it.
+    pub fn set_device(&mut self, device: Option<&str>) -> Result<(), Error> {
+        if let Some(device) = device {
+            if !gpapi::has_device(device) {
+                return Err(Error::new(ErrorKind::InvalidRequest(format!(
+                    "unknown device '{}'",
+                    device
+                ))));
+            }
+        }
+
+        self.device = device.map(String::from);
+        Ok(())
+    }
+
+    /// Makes every channel log in with the locale `hl` and the country `gl` rather than those of
+    /// its profile, failing with `InvalidRequest` if either is malformed. Given just a language,
+    /// e.g. `hl=de&gl=AT`, the locale is completed with the country to `de_AT`.
+    pub fn set_locale(&mut self, hl: Option<&str>, gl: Option<&str>) -> Result<(), Error> {
+        let (locale, country) = parse_locale(hl, gl)?;
+        self.locale = locale;
+        self.country = country;
+        Ok(())
+    }
+
+    /// The device a channel logs in as: the requested one, else the one of its profile, else
+    /// `DEVICE_NAME`. The locale and country are picked the same way, falling back to gpapi's.
+    fn client_key(&mut self, channel: &Channel) -> Result<ClientKey, Error> {
+        let profile = self.profile(channel)?;
+        let device = match self.device.clone().or(profile.device) {
+            Some(device) => device,
+            None => self.var("DEVICE_NAME")?,
+        };
+
+        Ok(ClientKey {
+            channel: channel.clone(),
+            device,
+            locale: self.locale.clone().or(profile.locale),
+            country: self.country.clone().or(profile.country),
+        })
+    }
+
+    pub async fn get_client(&mut self, channel: &Channel) -> Result<&mut GooglePlayClient, Error> {
+        let key = self.client_key(channel)?;
+
+        if !self.clients.contains_key(&key) {
+            let profile = Profile {
+                locale: key.locale.clone(),
+                country: key.country.clone(),
+                ..self.profile(channel)?
             };
+            let aas_token = self.var(&profile.token_secret)?;
 
-            let client = GooglePlayClient::new(&device_name, &email, &aas_token, channel);
-            self.clients.insert(channel, client);
-            self.initialized.insert(channel, false);
+            let client = GooglePlayClient::new(&profile, &key.device, &aas_token)?;
+            self.clients.insert(key.clone(), client);
+            self.initialized.insert(key.clone(), false);
         }
 
-        if !self.initialized.get(&channel).unwrap_or(&false) {
-            let client = self.clients.get_mut(&channel).unwrap();
-            client.initialize().await?;
-            self.initialized.insert(channel, true);
+        if !self.initialized.get(&key).unwrap_or(&false) {
+            let session = self.load_session(&key).await;
+            let client = self.clients.get_mut(&key).unwrap();
+
+            let restored = match session {
+                Some(session) => match client.restore_session(session) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        console_log!("Discarding stored session: {}", e);
+                        false
+                    }
+                },
+                None => false,
+            };
+
+            if !restored {
+                client.initialize().await?;
+                self.store_session(&key).await;
+            }
+            self.initialized.insert(key.clone(), true);
         }
 
-        Ok(self.clients.get(&channel).unwrap())
+        Ok(self.clients.get_mut(&key).unwrap())
     }
 
-    pub async fn get_details_with_fallback(
+    /// Keeps the stored session of a channel in sync after its client had to log in again,
+    /// and forgets the session entirely if that failed.
+    async fn sync_session(&mut self, channel: &Channel) {
+        let key = match self.client_key(channel) {
+            Ok(key) => key,
+            Err(_) => return,
+        };
+        let (logged_in, refreshed) = match self.clients.get_mut(&key) {
+            Some(client) => (client.is_logged_in(), client.take_session_refreshed()),
+            None => return,
+        };
+
+        if !logged_in {
+            self.initialized.insert(key.clone(), false);
+            self.delete_session(&key).await;
+        } else if refreshed {
+            self.store_session(&key).await;
+        }
+    }
+
+    async fn load_session(&self, key: &ClientKey) -> Option<Session> {
+        let kv = self.env.kv(SESSIONS_KV_BINDING).ok()?;
+
+        match kv.get(&session_key(key)).json::<Session>().await {
+            Ok(session) => session,
+            Err(e) => {
+                console_log!("Failed to load {} session: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    async fn store_session(&self, key: &ClientKey) {
+        let session = match self.clients.get(key).and_then(|c| c.session()) {
+            Some(session) => session,
+            None => return,
+        };
+        let kv = match self.env.kv(SESSIONS_KV_BINDING) {
+            Ok(kv) => kv,
+            Err(_) => return,
+        };
+
+        let now = Date::now().as_millis() / 1000;
+        let put = match kv.put(&session_key(key), &session) {
+            Ok(put) => put,
+            Err(e) => {
+                console_log!("Failed to store {} session: {:?}", key, e);
+                return;
+            }
+        };
+        let put = match session.auth_token_expiry {
+            Some(expiry) => {
+                let expiration = (expiry.max(0) as u64).saturating_sub(SESSION_EXPIRY_MARGIN_SECS);
+                if expiration < now + MIN_KV_TTL_SECS {
+                    return;
+                }
+                put.expiration(expiration)
+            }
+            None => put.expiration_ttl(DEFAULT_SESSION_TTL_SECS),
+        };
+
+        if let Err(e) = put.execute().await {
+            console_log!("Failed to store {} session: {:?}", key, e);
+        }
+    }
+
+    async fn delete_session(&self, key: &ClientKey) {
+        if let Ok(kv) = self.env.kv(SESSIONS_KV_BINDING) {
+            if let Err(e) = kv.delete(&session_key(key)).await {
+                console_log!("Failed to delete {} session: {:?}", key, e);
+            }
+        }
+    }
+
+    async fn fetch_details(
         &mut self,
         package_name: &str,
-        channel: Channel,
-    ) -> Result<Option<(Channel, googleplay_protobuf::DetailsResponse)>, String> {
-        if !channel.is_available_for_package(package_name) {
-            return Err(format!(
-                "Channel '{}' is not available for package '{}'",
-                channel, package_name
-            ));
+        channel: &Channel,
+    ) -> Result<Option<DetailsResponse>, Error> {
+        let result = self
+            .get_client(channel)
+            .await?
+            .get_details(package_name)
+            .await;
+        self.sync_session(channel).await;
+        result
+    }
+
+    /// Whether `channel` has a track for the app, either because it is allow-listed or because
+    /// it was detected before, or `None` if that is not known yet.
+    async fn known_track(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<Option<bool>, Error> {
+        if self.allow_list.is_none() {
+            self.allow_list = Some(AllowList::load(&self.env).await);
+        }
+        if let Some(allow_list) = &self.allow_list {
+            if allow_list.allows(channel, package_name) {
+                return Ok(Some(true));
+            }
         }
 
-        let client = self.get_client(channel).await?;
-        match client.get_details(package_name).await {
-            Ok(Some(response)) => return Ok(Some((channel, response))),
-            Ok(None) => return Ok(None),
-            Err(e) => return Err(e),
+        let key = tracks_key(&self.client_key(channel)?);
+        Ok(self.tracks.get(&key, package_name).await)
+    }
+
+    async fn set_track(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+        available: bool,
+    ) -> Result<(), Error> {
+        let key = tracks_key(&self.client_key(channel)?);
+        self.tracks.set(&key, package_name, available).await;
+        Ok(())
+    }
+
+    /// Fetches details from a channel other than stable, failing with `ChannelUnavailable` when its
+    /// account is served the same build as everyone else. `stable` is fetched if not given and
+    /// the track was not detected before.
+    async fn fetch_track_details(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+        stable: Option<&DetailsResponse>,
+    ) -> Result<Option<DetailsResponse>, Error> {
+        let known = self.known_track(package_name, channel).await?;
+        if known == Some(false) {
+            return Err(channel_unavailable(package_name, channel));
+        }
+
+        let details = match self.fetch_details(package_name, channel).await? {
+            Some(details) => details,
+            None => return Ok(None),
+        };
+
+        if known.is_none() {
+            let item = match details.item.as_ref() {
+                Some(item) => item,
+                None => return Ok(None),
+            };
+
+            let available = if is_enrolled(item) {
+                true
+            } else {
+                match stable {
+                    Some(stable) => has_track(item, stable.item.as_ref()),
+                    None => {
+                        let stable = self.fetch_details(package_name, &Channel::stable()).await?;
+                        has_track(item, stable.as_ref().and_then(|s| s.item.as_ref()))
+                    }
+                }
+            };
+
+            self.set_track(package_name, channel, available).await?;
+            if !available {
+                return Err(channel_unavailable(package_name, channel));
+            }
+        }
+
+        Ok(Some(details))
+    }
+
+    /// Fails with `ChannelUnavailable` unless the channel has a track for the app.
+    async fn ensure_track(&mut self, package_name: &str, channel: &Channel) -> Result<(), Error> {
+        if channel.is_stable() || self.known_track(package_name, channel).await? == Some(true) {
+            return Ok(());
         }
+
+        self.fetch_track_details(package_name, channel, None)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn get_details_with_fallback(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<Option<(Channel, googleplay_protobuf::DetailsResponse)>, Error> {
+        let result = if channel.is_stable() {
+            self.fetch_details(package_name, channel).await
+        } else {
+            self.fetch_track_details(package_name, channel, None).await
+        };
+        self.tracks.flush().await;
+
+        result.map(|details| details.map(|details| (channel.clone(), details)))
     }
 
     pub async fn get_details_multi(
         &mut self,
         package_name: &str,
-    ) -> Result<HashMap<Channel, googleplay_protobuf::DetailsResponse>, String> {
+    ) -> Result<HashMap<Channel, googleplay_protobuf::DetailsResponse>, Error> {
         let mut results = HashMap::new();
+        let track_channels = self.track_channels()?;
 
-        match self
-            .get_details_with_fallback(package_name, Channel::Stable)
-            .await
-        {
-            Ok(Some((_, response))) => {
-                results.insert(Channel::Stable, response);
-            }
+        let stable = match self.fetch_details(package_name, &Channel::stable()).await {
+            Ok(Some(response)) => response,
             Ok(None) => {
-                return Err(format!("App '{}' not found", package_name));
+                return Err(Error::new(ErrorKind::NotFound(package_name.to_string()))
+                    .with_channel(Channel::stable()));
             }
             Err(e) => {
                 console_log!("Error fetching {} for stable channel: {}", package_name, e);
                 return Err(e);
             }
-        }
+        };
 
-        if Channel::Beta.is_available_for_package(package_name) {
+        for channel in track_channels {
             match self
-                .get_client(Channel::Beta)
-                .await?
-                .get_details(package_name)
+                .fetch_track_details(package_name, &channel, Some(&stable))
                 .await
             {
                 Ok(Some(response)) => {
-                    results.insert(Channel::Beta, response);
+                    results.insert(channel, response);
                 }
+                Err(e) if matches!(e.kind(), ErrorKind::ChannelUnavailable(_)) => {}
                 Err(e) => {
-                    console_log!("Error fetching {} for beta channel: {}", package_name, e);
+                    console_log!(
+                        "Error fetching {} for {} channel: {}",
+                        package_name,
+                        channel,
+                        e
+                    );
                 }
                 _ => {}
             }
         }
+        self.tracks.flush().await;
 
-        if Channel::Alpha.is_available_for_package(package_name) {
-            match self
-                .get_client(Channel::Alpha)
-                .await?
-                .get_details(package_name)
-                .await
-            {
-                Ok(Some(response)) => {
-                    results.insert(Channel::Alpha, response);
+        results.insert(Channel::stable(), stable);
+        Ok(results)
+    }
+
+    pub async fn get_version(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<VersionInfo, Error> {
+        let track = self.ensure_track(package_name, channel).await;
+        self.tracks.flush().await;
+        track?;
+
+        let result = self
+            .get_client(channel)
+            .await?
+            .get_latest_version(package_name)
+            .await;
+        self.sync_session(channel).await;
+        result
+    }
+
+    /// The current release on every available channel, found the same way as by
+    /// `get_details_multi`.
+    pub async fn get_versions_multi(
+        &mut self,
+        package_name: &str,
+    ) -> Result<HashMap<Channel, VersionInfo>, Error> {
+        Ok(self
+            .get_details_multi(package_name)
+            .await?
+            .into_iter()
+            .filter_map(|(channel, details)| Some((channel, VersionInfo::from_details(&details)?)))
+            .collect())
+    }
+
+    async fn fetch_bulk_details(
+        &mut self,
+        package_names: &[&str],
+        channel: &Channel,
+    ) -> Result<HashMap<String, BulkDetailsEntry>, Error> {
+        let result = self
+            .get_client(channel)
+            .await?
+            .get_bulk_details(package_names)
+            .await;
+        self.sync_session(channel).await;
+        result
+    }
+
+    /// Looks up every package on all of its available channels with one request per channel.
+    /// Failing to reach the stable channel fails the whole lookup, while packages missing from
+    /// it are reported individually. Failing to reach another channel is reported on every
+    /// package that was looked up on it.
+    pub async fn get_bulk_details_multi(
+        &mut self,
+        package_names: &[String],
+    ) -> Result<HashMap<String, BulkDetailsResult>, Error> {
+        let track_channels = self.track_channels()?;
+        let stable_names: Vec<&str> = package_names.iter().map(String::as_str).collect();
+        let mut stable = match self
+            .fetch_bulk_details(&stable_names, &Channel::stable())
+            .await
+        {
+            Ok(items) => items,
+            Err(e) => {
+                console_log!("Error fetching bulk details for stable channel: {}", e);
+                return Err(e);
+            }
+        };
+
+        let mut results: HashMap<String, BulkDetailsResult> = HashMap::new();
+        for package_name in package_names {
+            let result = match stable.remove(package_name) {
+                Some(entry) => Ok(BulkDetails {
+                    entries: HashMap::from([(Channel::stable(), entry)]),
+                    ..Default::default()
+                }),
+                None => Err(Error::new(ErrorKind::NotFound(package_name.to_string()))
+                    .with_channel(Channel::stable())),
+            };
+            results.insert(package_name.to_string(), result);
+        }
+
+        for channel in track_channels {
+            let mut names: Vec<&str> = Vec::new();
+            let mut failure = None;
+            for package_name in package_names {
+                if !matches!(results.get(package_name), Some(Ok(_))) {
+                    continue;
                 }
+                match self.known_track(package_name, &channel).await {
+                    Ok(Some(false)) => {}
+                    Ok(_) => names.push(package_name),
+                    // The channel cannot be looked up, which is reported on the package below.
+                    Err(e) => {
+                        names.push(package_name);
+                        failure.get_or_insert(e);
+                    }
+                }
+            }
+            if names.is_empty() {
+                continue;
+            }
+
+            let items = match failure {
+                Some(e) => Err(e),
+                None => self.fetch_bulk_details(&names, &channel).await,
+            };
+            let items = match items {
+                Ok(items) => items,
                 Err(e) => {
-                    console_log!("Error fetching {} for alpha channel: {}", package_name, e);
+                    console_log!("Error fetching bulk details for {} channel: {}", channel, e);
+                    let summary = ErrorSummary::from(&e);
+                    for package_name in names {
+                        if let Some(Ok(details)) = results.get_mut(package_name) {
+                            details
+                                .channel_errors
+                                .insert(channel.clone(), summary.clone());
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            for (package_name, entry) in items {
+                let details = match results.get_mut(&package_name) {
+                    Some(Ok(details)) => details,
+                    _ => continue,
+                };
+
+                let available = match self.known_track(&package_name, &channel).await? {
+                    Some(available) => available,
+                    None => {
+                        let stable = details
+                            .entries
+                            .get(&Channel::stable())
+                            .and_then(|stable| stable.item.as_ref());
+                        let available = entry
+                            .item
+                            .as_ref()
+                            .is_some_and(|item| has_track(item, stable));
+                        self.set_track(&package_name, &channel, available).await?;
+                        available
+                    }
+                };
+
+                if available {
+                    details.entries.insert(channel.clone(), entry);
                 }
-                _ => {}
             }
         }
+        self.tracks.flush().await;
 
         Ok(results)
     }
 
+    /// Download info for a release of the app, the current one of the channel if `version_code`
+    /// is `None`, along with the version code it was resolved to.
     pub async fn get_download_info(
         &mut self,
         package_name: &str,
-        channel: Channel,
+        channel: &Channel,
         version_code: Option<i32>,
-    ) -> Result<Option<(Channel, DownloadInfo)>, String> {
-        if !channel.is_available_for_package(package_name) {
-            return Err(format!(
-                "Channel '{}' is not available for package '{}'",
-                channel, package_name
-            ));
-        }
+    ) -> Result<Option<(Channel, i32, DownloadInfo)>, Error> {
+        let track = self.ensure_track(package_name, channel).await;
+        self.tracks.flush().await;
+        track?;
 
         let client = self.get_client(channel).await?;
-        match client.get_download_info(package_name, version_code).await {
-            Ok(download_info) => Ok(Some((channel, download_info))),
+        let version_code = match version_code {
+            Some(version_code) => Ok(version_code),
+            None => client
+                .get_latest_version(package_name)
+                .await
+                .map(|version| version.version_code),
+        };
+        let result = match version_code {
+            Ok(version_code) => client
+                .get_download_info(package_name, Some(version_code))
+                .await
+                .map(|download_info| (version_code, download_info)),
+            Err(e) => Err(e),
+        };
+        self.sync_session(channel).await;
+
+        match result {
+            Ok((version_code, download_info)) => {
+                Ok(Some((channel.clone(), version_code, download_info)))
+            }
             Err(e) => Err(e),
         }
     }
+
+    fn ensure_not_stable(channel: &Channel) -> Result<(), Error> {
+        if channel.is_stable() {
+            return Err(Error::new(ErrorKind::InvalidRequest(
+                "the stable channel has no testing program".to_string(),
+            ))
+            .with_channel(channel.clone()));
+        }
+        Ok(())
+    }
+
+    /// Enrolls the channel's account in the app's testing program, or removes it from it, and
+    /// updates the detected track to match.
+    pub async fn set_testing_program(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+        subscribe: bool,
+    ) -> Result<bool, Error> {
+        Self::ensure_not_stable(channel)?;
+
+        let result = self
+            .get_client(channel)
+            .await?
+            .set_testing_program(package_name, subscribe)
+            .await;
+        self.sync_session(channel).await;
+
+        let confirmed = result?;
+        if confirmed {
+            self.set_track(package_name, channel, subscribe).await?;
+            self.tracks.flush().await;
+        }
+        Ok(confirmed)
+    }
+
+    pub async fn get_testing_program_info(
+        &mut self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<Option<TestingProgramInfo>, Error> {
+        Self::ensure_not_stable(channel)?;
+
+        let result = self
+            .get_client(channel)
+            .await?
+            .get_testing_program_info(package_name)
+            .await;
+        self.sync_session(channel).await;
+        result
+    }
 }
 
 pub type SharedClientRegistry = Arc<Mutex<ClientRegistry>>;
@@ -159,3 +800,87 @@ pub async fn create_registry(env: Env) -> SharedClientRegistry {
     let registry = ClientRegistry::new(env);
     Arc::new(Mutex::new(registry))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid_request(result: Result<(Option<String>, Option<String>), Error>) -> bool {
+        matches!(result, Err(e) if matches!(e.kind(), ErrorKind::InvalidRequest(_)))
+    }
+
+    #[test]
+    fn tracks_keys() {
+        let key = ClientKey {
+            channel: Channel::new("beta"),
+            device: String::from("px_7a"),
+            locale: Some(String::from("de_AT")),
+            country: Some(String::from("at")),
+        };
+        assert_eq!(tracks_key(&key), "tracks:beta:px_7a:at");
+
+        let other_locale = ClientKey {
+            locale: None,
+            ..key.clone()
+        };
+        assert_eq!(tracks_key(&other_locale), tracks_key(&key));
+        let other_device = ClientKey {
+            device: String::from("sm_s9_plus"),
+            ..key.clone()
+        };
+        assert_ne!(tracks_key(&other_device), tracks_key(&key));
+        let other_country = ClientKey {
+            country: None,
+            ..key.clone()
+        };
+        assert_eq!(tracks_key(&other_country), "tracks:beta:px_7a:");
+    }
+
+    #[test]
+    fn normalize_locales() {
+        assert_eq!(normalize_locale("de").as_deref(), Some("de"));
+        assert_eq!(normalize_locale("DE").as_deref(), Some("de"));
+        assert_eq!(normalize_locale("fil").as_deref(), Some("fil"));
+        assert_eq!(normalize_locale("de-AT").as_deref(), Some("de_AT"));
+        assert_eq!(normalize_locale("de_at").as_deref(), Some("de_AT"));
+
+        assert_eq!(normalize_locale(""), None);
+        assert_eq!(normalize_locale("d"), None);
+        assert_eq!(normalize_locale("deutsch"), None);
+        assert_eq!(normalize_locale("de-"), None);
+        assert_eq!(normalize_locale("de-AUT"), None);
+        assert_eq!(normalize_locale("de-AT-x"), None);
+        assert_eq!(normalize_locale("d3_AT"), None);
+    }
+
+    #[test]
+    fn parse_locales() {
+        assert_eq!(parse_locale(None, None).unwrap(), (None, None));
+        assert_eq!(
+            parse_locale(Some("de"), Some("AT")).unwrap(),
+            (Some("de_AT".to_string()), Some("at".to_string()))
+        );
+        assert_eq!(
+            parse_locale(Some("en-GB"), Some("de")).unwrap(),
+            (Some("en_GB".to_string()), Some("de".to_string()))
+        );
+        assert_eq!(
+            parse_locale(Some("de"), None).unwrap(),
+            (Some("de".to_string()), None)
+        );
+        assert_eq!(
+            parse_locale(None, Some("at")).unwrap(),
+            (None, Some("at".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_malformed_locales() {
+        assert!(is_invalid_request(parse_locale(Some("deutsch"), None)));
+        assert!(is_invalid_request(parse_locale(Some("de_"), None)));
+        assert!(is_invalid_request(parse_locale(Some(""), Some("at"))));
+        assert!(is_invalid_request(parse_locale(None, Some("aut"))));
+        assert!(is_invalid_request(parse_locale(None, Some("a1"))));
+        assert!(is_invalid_request(parse_locale(Some("de"), Some(""))));
+    }
+}
@@ -0,0 +1,154 @@
+use gpapi::error::{Error as GpapiError, ErrorKind as GpapiErrorKind};
+use serde::Serialize;
+use std::error::Error as StdError;
+use std::fmt;
+
+use crate::google_play_client::Channel;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    InvalidChannel(String),
+    InvalidRequest(String),
+    Unauthorized,
+    ChannelUnavailable(String),
+    NotFound(String),
+    Config(String),
+    Login(GpapiError),
+    Api(GpapiError),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    channel: Option<Channel>,
+    kind: ErrorKind,
+}
+
+/// The message and code of an `Error`, for reporting a single failure on several results.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorSummary {
+    pub error: String,
+    pub error_code: &'static str,
+}
+
+impl From<&Error> for ErrorSummary {
+    fn from(err: &Error) -> Self {
+        ErrorSummary {
+            error: err.to_string(),
+            error_code: err.code(),
+        }
+    }
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error {
+            channel: None,
+            kind,
+        }
+    }
+
+    /// Wraps an error returned by `Gpapi::login`.
+    pub fn login(err: GpapiError) -> Error {
+        Error::new(ErrorKind::Login(err))
+    }
+
+    /// Wraps an error returned by any other `Gpapi` call.
+    pub fn api(err: GpapiError) -> Error {
+        Error::new(ErrorKind::Api(err))
+    }
+
+    pub fn with_channel(mut self, channel: Channel) -> Error {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// The HTTP status the API answers with.
+    pub fn status(&self) -> u16 {
+        match &self.kind {
+            ErrorKind::InvalidChannel(_) | ErrorKind::InvalidRequest(_) => 400,
+            ErrorKind::Unauthorized => 401,
+            ErrorKind::ChannelUnavailable(_) | ErrorKind::NotFound(_) => 404,
+            ErrorKind::Config(_) => 503,
+            ErrorKind::Login(err) => match err.kind() {
+                GpapiErrorKind::Authentication | GpapiErrorKind::LoginRequired => 401,
+                GpapiErrorKind::TermsOfService | GpapiErrorKind::PermissionDenied => 403,
+                GpapiErrorKind::RateLimited => 429,
+                _ => 503,
+            },
+            ErrorKind::Api(err) => match err.kind() {
+                GpapiErrorKind::Authentication | GpapiErrorKind::LoginRequired => 401,
+                GpapiErrorKind::TermsOfService | GpapiErrorKind::PermissionDenied => 403,
+                GpapiErrorKind::InvalidApp | GpapiErrorKind::HttpStatus(404) => 404,
+                GpapiErrorKind::Server(_) => 422,
+                GpapiErrorKind::RateLimited => 429,
+                GpapiErrorKind::Network(_) => 503,
+                GpapiErrorKind::FileExists
+                | GpapiErrorKind::DirectoryExists
+                | GpapiErrorKind::DirectoryMissing => 500,
+                _ => 502,
+            },
+        }
+    }
+
+    /// A machine readable identifier of the error, returned as `error_code`.
+    pub fn code(&self) -> &'static str {
+        match &self.kind {
+            ErrorKind::InvalidChannel(_) => "invalid_channel",
+            ErrorKind::InvalidRequest(_) => "invalid_request",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::ChannelUnavailable(_) => "channel_unavailable",
+            ErrorKind::NotFound(_) => "not_found",
+            ErrorKind::Config(_) => "configuration_error",
+            ErrorKind::Login(err) => match err.kind() {
+                GpapiErrorKind::Authentication => "authentication_failed",
+                GpapiErrorKind::LoginRequired => "login_required",
+                GpapiErrorKind::TermsOfService => "terms_of_service",
+                GpapiErrorKind::PermissionDenied => "permission_denied",
+                GpapiErrorKind::RateLimited => "rate_limited",
+                _ => "login_failed",
+            },
+            ErrorKind::Api(err) => match err.kind() {
+                GpapiErrorKind::Authentication => "authentication_failed",
+                GpapiErrorKind::LoginRequired => "login_required",
+                GpapiErrorKind::TermsOfService => "terms_of_service",
+                GpapiErrorKind::PermissionDenied => "permission_denied",
+                GpapiErrorKind::InvalidApp | GpapiErrorKind::HttpStatus(404) => "app_unavailable",
+                GpapiErrorKind::InvalidResponse | GpapiErrorKind::Decode(_) => "invalid_response",
+                GpapiErrorKind::RateLimited => "rate_limited",
+                GpapiErrorKind::Server(_) => "play_error",
+                GpapiErrorKind::Network(_) => "network_error",
+                GpapiErrorKind::FileExists
+                | GpapiErrorKind::DirectoryExists
+                | GpapiErrorKind::DirectoryMissing => "internal_error",
+                _ => "upstream_error",
+            },
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(channel) = &self.channel {
+            write!(f, "{} channel: ", channel)?;
+        }
+
+        match &self.kind {
+            ErrorKind::InvalidChannel(channel) => write!(f, "Invalid channel '{}'", channel),
+            ErrorKind::InvalidRequest(err) => write!(f, "Invalid request: {}", err),
+            ErrorKind::Unauthorized => write!(f, "Missing or invalid admin token"),
+            ErrorKind::ChannelUnavailable(package_name) => {
+                write!(f, "Channel is not available for package '{}'", package_name)
+            }
+            ErrorKind::NotFound(package_name) => write!(f, "App '{}' not found", package_name),
+            ErrorKind::Config(err) => write!(f, "Misconfigured worker: {}", err),
+            ErrorKind::Login(err) => write!(f, "Login failed: {}", err),
+            ErrorKind::Api(err) => err.fmt(f),
+        }
+    }
+}
@@ -1,93 +1,229 @@
-use googleplay_protobuf::DetailsResponse;
-use gpapi::{DownloadInfo, Gpapi};
-use std::collections::HashSet;
-
-use lazy_static::lazy_static;
-
-lazy_static! {
-    pub static ref BETA_PACKAGES: HashSet<&'static str> = {
-        let mut set = HashSet::new();
-        set.insert("com.discord");
-        set
-    };
-    pub static ref ALPHA_PACKAGES: HashSet<&'static str> = {
-        let mut set = HashSet::new();
-        set.insert("com.discord");
-        set
-    };
-}
+use googleplay_protobuf::{BulkDetailsEntry, DetailsResponse, TestingProgramInfo};
+use gpapi::error::{Error as GpapiError, ErrorKind as GpapiErrorKind};
+use gpapi::{DownloadInfo, Gpapi, Session, VersionInfo};
+use std::collections::HashMap;
+use worker::console_log;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Channel {
-    Stable,
-    Beta,
-    Alpha,
-}
+use crate::error::{Error, ErrorKind};
+use crate::profiles::Profile;
+
+/// The name of a configured profile, e.g. `stable`, `beta` or `internal`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Channel(String);
 
 impl std::fmt::Display for Channel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Channel::Stable => write!(f, "stable"),
-            Channel::Beta => write!(f, "beta"),
-            Channel::Alpha => write!(f, "alpha"),
-        }
+        write!(f, "{}", self.0)
     }
 }
 
 impl Channel {
-    pub fn from_str(s: &str) -> Result<Self, String> {
-        match s.to_lowercase().as_str() {
-            "stable" => Ok(Channel::Stable),
-            "beta" => Ok(Channel::Beta),
-            "alpha" => Ok(Channel::Alpha),
-            _ => Err(format!("Invalid Channel: {}", s)),
-        }
+    pub fn new(name: &str) -> Self {
+        Channel(name.to_lowercase())
     }
 
-    pub fn is_available_for_package(self, package_name: &str) -> bool {
-        match self {
-            Channel::Stable => true,
-            Channel::Beta => BETA_PACKAGES.contains(package_name),
-            Channel::Alpha => ALPHA_PACKAGES.contains(package_name),
-        }
+    /// The channel every other one is compared against.
+    pub fn stable() -> Self {
+        Channel::new("stable")
+    }
+
+    pub fn is_stable(&self) -> bool {
+        self.0 == "stable"
     }
 }
 
 pub struct GooglePlayClient {
     client: Gpapi,
     channel: Channel,
+    logged_in: bool,
+    session_refreshed: bool,
+}
+
+/// Play answers requests made with an expired auth token with 401, which gpapi reports as
+/// `LoginRequired`.
+fn is_auth_failure(err: &GpapiError) -> bool {
+    matches!(err.kind(), GpapiErrorKind::LoginRequired)
 }
 
 impl GooglePlayClient {
-    pub fn new(device_name: &str, email: &str, aas_token: &str, channel: Channel) -> Self {
-        let mut client = Gpapi::new(device_name, email);
+    /// Fails with a `Config` error if gpapi has no device profile named `device_name`.
+    pub fn new(profile: &Profile, device_name: &str, aas_token: &str) -> Result<Self, Error> {
+        let mut client = Gpapi::try_new(device_name, &profile.email).map_err(|e| {
+            Error::new(ErrorKind::Config(e.to_string())).with_channel(profile.channel())
+        })?;
         client.set_aas_token(aas_token);
+        if let Some(locale) = &profile.locale {
+            client.set_locale(locale);
+        }
+        if let Some(country) = &profile.country {
+            client.set_country(country);
+        }
 
-        Self { client, channel }
+        Ok(Self {
+            client,
+            channel: profile.channel(),
+            logged_in: false,
+            session_refreshed: false,
+        })
     }
 
-    pub async fn initialize(&mut self) -> Result<(), String> {
-        self.client
+    pub async fn initialize(&mut self) -> Result<(), Error> {
+        let result = self
+            .client
             .login()
             .await
-            .map_err(|e| format!("Login error for {} channel: {:?}", self.channel, e))
+            .map_err(|e| Error::login(e).with_channel(self.channel.clone()));
+        self.logged_in = result.is_ok();
+        result
     }
 
-    pub async fn get_details(&self, package_name: &str) -> Result<Option<DetailsResponse>, String> {
+    /// Whether the client holds a usable session, which is no longer the case once logging in
+    /// again after an expired auth token failed.
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in
+    }
+
+    /// Whether the client logged in again since the last call, meaning its session changed.
+    pub fn take_session_refreshed(&mut self) -> bool {
+        std::mem::take(&mut self.session_refreshed)
+    }
+
+    async fn relogin(&mut self) -> Result<(), Error> {
+        console_log!(
+            "Auth token for {} channel expired, logging in again",
+            self.channel
+        );
+        self.initialize().await?;
+        self.session_refreshed = true;
+        Ok(())
+    }
+
+    pub fn session(&self) -> Option<Session> {
+        self.client.session()
+    }
+
+    pub fn restore_session(&mut self, session: Session) -> Result<(), Error> {
         self.client
-            .details(package_name)
-            .await
-            .map_err(|e| format!("API error for {} channel: {:?}", self.channel, e))
+            .restore_session(session)
+            .map_err(|e| Error::login(e).with_channel(self.channel.clone()))?;
+        self.logged_in = true;
+        Ok(())
+    }
+
+    pub async fn get_details(
+        &mut self,
+        package_name: &str,
+    ) -> Result<Option<DetailsResponse>, Error> {
+        let result = match self.client.details(package_name).await {
+            Err(e) if is_auth_failure(&e) => {
+                self.relogin().await?;
+                self.client.details(package_name).await
+            }
+            result => result,
+        };
+
+        result.map_err(|e| Error::api(e).with_channel(self.channel.clone()))
+    }
+
+    pub async fn get_latest_version(&mut self, package_name: &str) -> Result<VersionInfo, Error> {
+        let result = match self.client.latest_version(package_name).await {
+            Err(e) if is_auth_failure(&e) => {
+                self.relogin().await?;
+                self.client.latest_version(package_name).await
+            }
+            result => result,
+        };
+
+        result.map_err(|e| Error::api(e).with_channel(self.channel.clone()))
+    }
+
+    /// Looks up many packages in a single request. Packages unknown to Google Play are left out
+    /// of the returned map.
+    pub async fn get_bulk_details(
+        &mut self,
+        package_names: &[&str],
+    ) -> Result<HashMap<String, BulkDetailsEntry>, Error> {
+        let result = match self.client.bulk_details(package_names).await {
+            Err(e) if is_auth_failure(&e) => {
+                self.relogin().await?;
+                self.client.bulk_details(package_names).await
+            }
+            result => result,
+        };
+
+        let response = result.map_err(|e| Error::api(e).with_channel(self.channel.clone()))?;
+
+        Ok(response
+            .map(|response| response.entry)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|entry| Some((entry.item.as_ref()?.id.clone()?, entry)))
+            .collect())
     }
 
     pub async fn get_download_info(
-        &self,
+        &mut self,
         package_name: &str,
         version_code: Option<i32>,
-    ) -> Result<DownloadInfo, String> {
-        self.client
+    ) -> Result<DownloadInfo, Error> {
+        let result = match self
+            .client
             .get_download_info(package_name, version_code)
             .await
-            .map_err(|e| format!("API error for {} channel: {:?}", self.channel, e))
+        {
+            Err(e) if is_auth_failure(&e) => {
+                self.relogin().await?;
+                self.client
+                    .get_download_info(package_name, version_code)
+                    .await
+            }
+            result => result,
+        };
+
+        result.map_err(|e| Error::api(e).with_channel(self.channel.clone()))
+    }
+
+    /// Joins or leaves the app's testing program, returning whether Play confirmed it.
+    pub async fn set_testing_program(
+        &mut self,
+        package_name: &str,
+        subscribe: bool,
+    ) -> Result<bool, Error> {
+        let result = match self.testing_program(package_name, subscribe).await {
+            Err(e) if is_auth_failure(&e) => {
+                self.relogin().await?;
+                self.testing_program(package_name, subscribe).await
+            }
+            result => result,
+        };
+
+        result.map_err(|e| Error::api(e).with_channel(self.channel.clone()))
+    }
+
+    async fn testing_program(
+        &self,
+        package_name: &str,
+        subscribe: bool,
+    ) -> Result<bool, GpapiError> {
+        if subscribe {
+            self.client.join_testing_program(package_name).await
+        } else {
+            self.client.leave_testing_program(package_name).await
+        }
+    }
+
+    pub async fn get_testing_program_info(
+        &mut self,
+        package_name: &str,
+    ) -> Result<Option<TestingProgramInfo>, Error> {
+        let result = match self.client.testing_program_info(package_name).await {
+            Err(e) if is_auth_failure(&e) => {
+                self.relogin().await?;
+                self.client.testing_program_info(package_name).await
+            }
+            result => result,
+        };
+
+        result.map_err(|e| Error::api(e).with_channel(self.channel.clone()))
     }
 }
@@ -1,11 +1,17 @@
+mod allow_list;
 mod client_registry;
+mod error;
 mod google_play_client;
+mod profiles;
 mod serializable_types;
+mod track_cache;
 
 use client_registry::{create_registry, SharedClientRegistry};
+use error::{Error as SniffError, ErrorKind as SniffErrorKind, ErrorSummary};
 use google_play_client::Channel;
-use serde::Serialize;
-use serializable_types::SerializableDetailsResponse;
+use gpapi::DownloadInfo;
+use serde::{Deserialize, Serialize};
+use serializable_types::{SerializableBulkDetailsEntry, SerializableDetailsResponse};
 use std::collections::HashMap;
 use worker::*;
 
@@ -14,6 +20,7 @@ struct ApiResponse<T> {
     success: bool,
     data: Option<T>,
     error: Option<String>,
+    error_code: Option<&'static str>,
 }
 
 #[derive(Serialize)]
@@ -21,6 +28,49 @@ struct MultiChannelApiResponse<T> {
     success: bool,
     data: Option<HashMap<String, T>>,
     error: Option<String>,
+    error_code: Option<&'static str>,
+    /// Channels that could not be checked, only reported by bulk lookups.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_errors: Option<HashMap<String, ErrorSummary>>,
+}
+
+/// The secret admin requests have to present as a bearer token.
+const ADMIN_TOKEN_SECRET: &str = "ADMIN_TOKEN";
+
+/// The most packages a single bulk lookup accepts.
+const MAX_BULK_PACKAGES: usize = 500;
+
+#[derive(Deserialize)]
+struct BulkDetailsRequest {
+    packages: Vec<String>,
+}
+
+/// A `/v2/download` response, the files along with the version they belong to.
+#[derive(Serialize)]
+struct DownloadResponse {
+    version_code: i32,
+    #[serde(flatten)]
+    download_info: DownloadInfo,
+}
+
+#[derive(Serialize)]
+struct TestingProgramStatus {
+    /// Whether the app offers a testing program to the account, only reported when reading the
+    /// enrollment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available: Option<bool>,
+    subscribed: bool,
+}
+
+fn error_response(error: SniffError) -> Result<Response> {
+    let response = ApiResponse::<()> {
+        success: false,
+        data: None,
+        error: Some(error.to_string()),
+        error_code: Some(error.code()),
+    };
+
+    Ok(Response::from_json(&response)?.with_status(error.status()))
 }
 
 struct AppState {
@@ -31,14 +81,20 @@ async fn handle_details_request(
     _: Request,
     state: &AppState,
     package_name: &str,
-    channel: Channel,
+    channel: &str,
 ) -> Result<Response> {
-    let result = state
+    let mut registry = state
         .client_registry
         .lock()
-        .expect("Failed to lock client registry")
-        .get_details_with_fallback(package_name, channel)
-        .await;
+        .expect("Failed to lock client registry");
+    let result = match registry.channel(channel) {
+        Ok(channel) => {
+            registry
+                .get_details_with_fallback(package_name, &channel)
+                .await
+        }
+        Err(e) => Err(e),
+    };
 
     match result {
         Ok(Some((_, details))) => {
@@ -46,73 +102,85 @@ async fn handle_details_request(
                 success: true,
                 data: Some(SerializableDetailsResponse(details)),
                 error: None,
+                error_code: None,
             };
 
             Ok(Response::from_json(&response)?)
         }
-        Ok(None) => {
-            let response = ApiResponse::<SerializableDetailsResponse> {
-                success: false,
-                data: None,
-                error: Some(format!("App '{}' not found", package_name)),
-            };
-
-            Ok(Response::from_json(&response)?.with_status(404))
-        }
-        Err(e) => {
-            let response = ApiResponse::<SerializableDetailsResponse> {
-                success: false,
-                data: None,
-                error: Some(e),
-            };
-
-            Ok(Response::from_json(&response)?.with_status(500))
-        }
+        Ok(None) => error_response(
+            SniffError::new(SniffErrorKind::NotFound(package_name.to_string()))
+                .with_channel(Channel::new(channel)),
+        ),
+        Err(e) => error_response(e),
     }
 }
 
+/// How a download response lays out the files of a release.
+#[derive(Clone, Copy)]
+enum DownloadFormat {
+    /// `/v1`: `[base_url, [[split_name, split_url], ...], [[filename, url], ...]]`.
+    Legacy,
+    /// `/v2`: gpapi's `DownloadInfo` object, including sizes and hashes.
+    Structured,
+}
+
 async fn handle_download_request(
     _: Request,
     state: &AppState,
     package_name: &str,
-    channel: Channel,
+    channel: &str,
     version_code: Option<i32>,
+    format: DownloadFormat,
 ) -> Result<Response> {
-    let result = state
+    let mut registry = state
         .client_registry
         .lock()
-        .expect("Failed to lock client registry")
-        .get_download_info(package_name, channel, version_code)
-        .await;
+        .expect("Failed to lock client registry");
+    let result = match registry.channel(channel) {
+        Ok(channel) => {
+            registry
+                .get_download_info(package_name, &channel, version_code)
+                .await
+        }
+        Err(e) => Err(e),
+    };
 
     match result {
-        Ok(Some((_, url))) => {
-            let response = ApiResponse {
-                success: true,
-                data: Some(url),
-                error: None,
+        Ok(Some((_, version_code, download_info))) => {
+            let cookie = download_info.cookie_header();
+            let response = match format {
+                DownloadFormat::Legacy => Response::from_json(&ApiResponse {
+                    success: true,
+                    data: Some(download_info.into_legacy()),
+                    error: None,
+                    error_code: None,
+                })?,
+                DownloadFormat::Structured => Response::from_json(&ApiResponse {
+                    success: true,
+                    data: Some(DownloadResponse {
+                        version_code,
+                        download_info,
+                    }),
+                    error: None,
+                    error_code: None,
+                })?,
             };
 
-            Ok(Response::from_json(&response)?)
-        }
-        Ok(None) => {
-            let response = ApiResponse::<String> {
-                success: false,
-                data: None,
-                error: Some(format!("App '{}' not found", package_name)),
-            };
+            let mut headers = Headers::new();
 
-            Ok(Response::from_json(&response)?.with_status(404))
-        }
-        Err(e) => {
-            let response = ApiResponse::<String> {
-                success: false,
-                data: None,
-                error: Some(e),
-            };
+            headers.set("Content-Type", "application/json")?;
+            headers.set("X-Version-Code", &version_code.to_string())?;
+            if let Some(cookie) = cookie {
+                headers.set("X-Download-Cookie", &cookie)?;
+            }
 
-            Ok(Response::from_json(&response)?.with_status(500))
+            Ok(response.with_headers(headers))
         }
+        Ok(None) => error_response(
+            SniffError::new(SniffErrorKind::NotFound(package_name.to_string()))
+                .with_channel(Channel::new(channel)),
+        ),
+        Err(e) => error_response(e),
     }
 }
 
@@ -142,6 +210,8 @@ async fn handle_details_multi_request(
                 success: true,
                 data: Some(serialized_map),
                 error: None,
+                error_code: None,
+                channel_errors: None,
             };
 
             let mut headers = Headers::new();
@@ -151,15 +221,263 @@ async fn handle_details_multi_request(
 
             Ok(Response::from_json(&response)?.with_headers(headers))
         }
-        Err(e) => {
-            let response = MultiChannelApiResponse::<SerializableDetailsResponse> {
-                success: false,
-                data: None,
-                error: Some(e),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn handle_version_request(
+    state: &AppState,
+    package_name: &str,
+    channel: &str,
+) -> Result<Response> {
+    let mut registry = state
+        .client_registry
+        .lock()
+        .expect("Failed to lock client registry");
+    let result = match registry.channel(channel) {
+        Ok(channel) => registry.get_version(package_name, &channel).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(version) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(version),
+                error: None,
+                error_code: None,
+            };
+
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+async fn handle_version_multi_request(state: &AppState, package_name: &str) -> Result<Response> {
+    match state
+        .client_registry
+        .lock()
+        .expect("Failed to lock client registry")
+        .get_versions_multi(package_name)
+        .await
+    {
+        Ok(versions) => {
+            let response = MultiChannelApiResponse {
+                success: true,
+                data: Some(
+                    versions
+                        .into_iter()
+                        .map(|(channel, version)| (channel.to_string(), version))
+                        .collect(),
+                ),
+                error: None,
+                error_code: None,
+                channel_errors: None,
+            };
+
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+/// Parses the version code of a download route, where `latest` stands for the current release.
+fn parse_version_code(version_code: &str) -> std::result::Result<Option<i32>, SniffError> {
+    if version_code.eq_ignore_ascii_case("latest") {
+        return Ok(None);
+    }
+
+    match version_code.parse::<i32>() {
+        Ok(version_code) if version_code > 0 => Ok(Some(version_code)),
+        _ => Err(SniffError::new(SniffErrorKind::InvalidRequest(format!(
+            "malformed version code '{}'",
+            version_code
+        )))),
+    }
+}
+
+/// Trims and deduplicates the requested package names, keeping their order.
+fn bulk_package_names<I, S>(packages: I) -> std::result::Result<Vec<String>, SniffError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut package_names: Vec<String> = Vec::new();
+    for package_name in packages {
+        let package_name = package_name.as_ref().trim();
+        if !package_name.is_empty() && !package_names.iter().any(|p| p == package_name) {
+            package_names.push(package_name.to_string());
+        }
+    }
+
+    if package_names.is_empty() {
+        return Err(SniffError::new(SniffErrorKind::InvalidRequest(
+            "no packages given".to_string(),
+        )));
+    }
+    if package_names.len() > MAX_BULK_PACKAGES {
+        return Err(SniffError::new(SniffErrorKind::InvalidRequest(format!(
+            "at most {} packages can be looked up at once",
+            MAX_BULK_PACKAGES
+        ))));
+    }
+
+    Ok(package_names)
+}
+
+async fn handle_bulk_details_request(
+    state: &AppState,
+    package_names: &[String],
+) -> Result<Response> {
+    let result = state
+        .client_registry
+        .lock()
+        .expect("Failed to lock client registry")
+        .get_bulk_details_multi(package_names)
+        .await;
+
+    match result {
+        Ok(results) => {
+            let data: HashMap<String, MultiChannelApiResponse<SerializableBulkDetailsEntry>> =
+                results
+                    .into_iter()
+                    .map(|(package_name, result)| {
+                        let response = match result {
+                            Ok(details) => MultiChannelApiResponse {
+                                success: true,
+                                data: Some(
+                                    details
+                                        .entries
+                                        .into_iter()
+                                        .map(|(channel, details)| {
+                                            (
+                                                channel.to_string(),
+                                                SerializableBulkDetailsEntry(details),
+                                            )
+                                        })
+                                        .collect(),
+                                ),
+                                error: None,
+                                error_code: None,
+                                channel_errors: (!details.channel_errors.is_empty()).then(|| {
+                                    details
+                                        .channel_errors
+                                        .into_iter()
+                                        .map(|(channel, error)| (channel.to_string(), error))
+                                        .collect()
+                                }),
+                            },
+                            Err(e) => MultiChannelApiResponse {
+                                success: false,
+                                data: None,
+                                error: Some(e.to_string()),
+                                error_code: Some(e.code()),
+                                channel_errors: None,
+                            },
+                        };
+                        (package_name, response)
+                    })
+                    .collect();
+
+            let response = ApiResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+                error_code: None,
+            };
+
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Admin routes require `Authorization: Bearer <ADMIN_TOKEN>` and are disabled while the secret is
+/// not set.
+fn authorize_admin(req: &Request, env: &Env) -> std::result::Result<(), SniffError> {
+    let token = env.secret(ADMIN_TOKEN_SECRET).map_err(|_| {
+        SniffError::new(SniffErrorKind::Config(format!(
+            "'{}' is not set",
+            ADMIN_TOKEN_SECRET
+        )))
+    })?;
+    let header = req.headers().get("Authorization").ok().flatten();
+
+    match header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(given) if constant_time_eq(given.as_bytes(), token.to_string().as_bytes()) => Ok(()),
+        _ => Err(SniffError::new(SniffErrorKind::Unauthorized)),
+    }
+}
+
+/// Reads the enrollment of the channel's account in the app's testing program if `subscribe` is
+/// `None`, and joins or leaves the program otherwise.
+async fn handle_testing_program_request(
+    req: Request,
+    ctx: RouteContext<AppState>,
+    subscribe: Option<bool>,
+) -> Result<Response> {
+    if let Err(e) = authorize_admin(&req, &ctx.env) {
+        return error_response(e);
+    }
+
+    let package_name = ctx.param("package_name").unwrap();
+    let channel = ctx.param("channel").unwrap();
+
+    let mut registry = ctx
+        .data
+        .client_registry
+        .lock()
+        .expect("Failed to lock client registry");
+    let channel = match registry.channel(channel) {
+        Ok(channel) => channel,
+        Err(e) => return error_response(e),
+    };
+
+    let result = match subscribe {
+        Some(subscribe) => match registry
+            .set_testing_program(package_name, &channel, subscribe)
+            .await
+        {
+            Ok(true) => Ok(TestingProgramStatus {
+                available: None,
+                subscribed: subscribe,
+            }),
+            // Play did not confirm the change, so report the enrollment it still has.
+            Ok(false) => registry
+                .get_testing_program_info(package_name, &channel)
+                .await
+                .map(|info| TestingProgramStatus {
+                    available: None,
+                    subscribed: info.and_then(|info| info.subscribed).unwrap_or(false),
+                }),
+            Err(e) => Err(e),
+        },
+        None => registry
+            .get_testing_program_info(package_name, &channel)
+            .await
+            .map(|info| TestingProgramStatus {
+                available: Some(info.is_some()),
+                subscribed: info.and_then(|info| info.subscribed).unwrap_or(false),
+            }),
+    };
+
+    match result {
+        Ok(status) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(status),
+                error: None,
+                error_code: None,
             };
 
-            Ok(Response::from_json(&response)?.with_status(500))
+            Ok(Response::from_json(&response)?)
         }
+        Err(e) => error_response(e),
     }
 }
 
@@ -168,6 +486,28 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
     let client_registry = create_registry(env.clone()).await;
+
+    // `?device=`, `?hl=` and `?gl=` make every channel log in as another device, or with another
+    // locale and country, for this request
+    let url = req.url()?;
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let (device, hl, gl) = (query("device"), query("hl"), query("gl"));
+    let overrides_set = {
+        let mut registry = client_registry
+            .lock()
+            .expect("Failed to lock client registry");
+        registry
+            .set_device(device.as_deref())
+            .and_then(|_| registry.set_locale(hl.as_deref(), gl.as_deref()))
+    };
+    if let Err(e) = overrides_set {
+        return error_response(e);
+    }
+
     let state = AppState { client_registry };
 
     let router = Router::with_data(state);
@@ -178,6 +518,44 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
 
             Response::redirect(url)
         })
+        .get("/v1/devices", |_req, _ctx| {
+            let response = ApiResponse {
+                success: true,
+                data: Some(gpapi::devices()),
+                error: None,
+                error_code: None,
+            };
+
+            Response::from_json(&response)
+        })
+        .get_async("/v1/details/bulk", |req, ctx| async move {
+            let url = req.url()?;
+            let packages = url
+                .query_pairs()
+                .filter(|(key, _)| key == "packages")
+                .flat_map(|(_, value)| value.split(',').map(str::to_string).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+
+            match bulk_package_names(packages) {
+                Ok(package_names) => handle_bulk_details_request(&ctx.data, &package_names).await,
+                Err(e) => error_response(e),
+            }
+        })
+        .post_async("/v1/details/bulk", |mut req, ctx| async move {
+            let request = match req.json::<BulkDetailsRequest>().await {
+                Ok(request) => request,
+                Err(e) => {
+                    return error_response(SniffError::new(SniffErrorKind::InvalidRequest(
+                        e.to_string(),
+                    )))
+                }
+            };
+
+            match bulk_package_names(request.packages) {
+                Ok(package_names) => handle_bulk_details_request(&ctx.data, &package_names).await,
+                Err(e) => error_response(e),
+            }
+        })
         .get_async("/v1/details/:package_name", |req, ctx| async move {
             let package_name = ctx.param("package_name").unwrap();
             handle_details_multi_request(req, &ctx.data, package_name).await
@@ -188,18 +566,47 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
                 let package_name = ctx.param("package_name").unwrap();
                 let channel = ctx.param("channel").unwrap();
 
-                match Channel::from_str(channel) {
-                    Ok(track) => handle_details_request(req, &ctx.data, package_name, track).await,
-                    Err(e) => {
-                        let response = ApiResponse::<()> {
-                            success: false,
-                            data: None,
-                            error: Some(e),
-                        };
-
-                        Ok(Response::from_json(&response)?.with_status(400))
-                    }
-                }
+                handle_details_request(req, &ctx.data, package_name, channel).await
+            },
+        )
+        .get_async("/v1/version/:package_name", |_req, ctx| async move {
+            let package_name = ctx.param("package_name").unwrap();
+            handle_version_multi_request(&ctx.data, package_name).await
+        })
+        .get_async(
+            "/v1/version/:package_name/:channel",
+            |_req, ctx| async move {
+                let package_name = ctx.param("package_name").unwrap();
+                let channel = ctx.param("channel").unwrap();
+                handle_version_request(&ctx.data, package_name, channel).await
+            },
+        )
+        .get_async("/v1/download/:package_name", |req, ctx| async move {
+            let package_name = ctx.param("package_name").unwrap();
+            handle_download_request(
+                req,
+                &ctx.data,
+                package_name,
+                "stable",
+                None,
+                DownloadFormat::Legacy,
+            )
+            .await
+        })
+        .get_async(
+            "/v1/download/:package_name/:channel",
+            |req, ctx| async move {
+                let package_name = ctx.param("package_name").unwrap();
+                let channel = ctx.param("channel").unwrap();
+                handle_download_request(
+                    req,
+                    &ctx.data,
+                    package_name,
+                    channel,
+                    None,
+                    DownloadFormat::Legacy,
+                )
+                .await
             },
         )
         .get_async(
@@ -207,32 +614,83 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
             |req, ctx| async move {
                 let package_name = ctx.param("package_name").unwrap();
                 let channel = ctx.param("channel").unwrap();
-                let version_code = ctx.param("version_code").unwrap();
-                let version_code: i32 = version_code.parse().unwrap_or(0);
-
-                match Channel::from_str(channel) {
-                    Ok(track) => {
-                        handle_download_request(
-                            req,
-                            &ctx.data,
-                            package_name,
-                            track,
-                            Some(version_code),
-                        )
-                        .await
-                    }
-                    Err(e) => {
-                        let response = ApiResponse::<()> {
-                            success: false,
-                            data: None,
-                            error: Some(e),
-                        };
+                let version_code = match parse_version_code(ctx.param("version_code").unwrap()) {
+                    Ok(version_code) => version_code,
+                    Err(e) => return error_response(e),
+                };
 
-                        Ok(Response::from_json(&response)?.with_status(400))
-                    }
-                }
+                handle_download_request(
+                    req,
+                    &ctx.data,
+                    package_name,
+                    channel,
+                    version_code,
+                    DownloadFormat::Legacy,
+                )
+                .await
+            },
+        )
+        .get_async("/v2/download/:package_name", |req, ctx| async move {
+            let package_name = ctx.param("package_name").unwrap();
+            handle_download_request(
+                req,
+                &ctx.data,
+                package_name,
+                "stable",
+                None,
+                DownloadFormat::Structured,
+            )
+            .await
+        })
+        .get_async(
+            "/v2/download/:package_name/:channel",
+            |req, ctx| async move {
+                let package_name = ctx.param("package_name").unwrap();
+                let channel = ctx.param("channel").unwrap();
+                handle_download_request(
+                    req,
+                    &ctx.data,
+                    package_name,
+                    channel,
+                    None,
+                    DownloadFormat::Structured,
+                )
+                .await
             },
         )
+        .get_async(
+            "/v2/download/:package_name/:channel/:version_code",
+            |req, ctx| async move {
+                let package_name = ctx.param("package_name").unwrap();
+                let channel = ctx.param("channel").unwrap();
+                let version_code = match parse_version_code(ctx.param("version_code").unwrap()) {
+                    Ok(version_code) => version_code,
+                    Err(e) => return error_response(e),
+                };
+
+                handle_download_request(
+                    req,
+                    &ctx.data,
+                    package_name,
+                    channel,
+                    version_code,
+                    DownloadFormat::Structured,
+                )
+                .await
+            },
+        )
+        .get_async(
+            "/v1/admin/testing-program/:package_name/:channel",
+            |req, ctx| async move { handle_testing_program_request(req, ctx, None).await },
+        )
+        .post_async(
+            "/v1/admin/testing-program/:package_name/:channel",
+            |req, ctx| async move { handle_testing_program_request(req, ctx, Some(true)).await },
+        )
+        .delete_async(
+            "/v1/admin/testing-program/:package_name/:channel",
+            |req, ctx| async move { handle_testing_program_request(req, ctx, Some(false)).await },
+        )
         .run(req, env)
         .await
 }
@@ -0,0 +1,70 @@
+use serde::Deserialize;
+use worker::Env;
+
+use crate::error::{Error, ErrorKind};
+use crate::google_play_client::Channel;
+
+/// The worker var holding the JSON list of profiles.
+const PROFILES_VAR: &str = "PROFILES";
+/// The profiles configured through `*_EMAIL` and `*_AAS_TOKEN` vars when `PROFILES` is not set.
+const LEGACY_PROFILES: [(&str, &str); 3] =
+    [("stable", "STABLE"), ("beta", "BETA"), ("alpha", "ALPHA")];
+
+/// A Google Play account apps are looked up with, exposed under its name as a channel.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub email: String,
+    /// The secret holding the account's AAS token.
+    pub token_secret: String,
+    /// The device codename to log in as, `DEVICE_NAME` if unset.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// E.g. `de_DE`, gpapi's default if unset.
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// E.g. `de`, gpapi's default if unset.
+    #[serde(default)]
+    pub country: Option<String>,
+}
+
+impl Profile {
+    pub fn channel(&self) -> Channel {
+        Channel::new(&self.name)
+    }
+}
+
+/// Reads the profiles from `PROFILES`, or from the legacy per channel vars if that is not set.
+/// Either way a `stable` profile is required, as every other profile is compared against it.
+pub fn load_profiles(env: &Env) -> Result<Vec<Profile>, Error> {
+    let profiles = match env.var(PROFILES_VAR) {
+        Ok(var) => serde_json::from_str::<Vec<Profile>>(&var.to_string()).map_err(|e| {
+            Error::new(ErrorKind::Config(format!(
+                "malformed '{}': {}",
+                PROFILES_VAR, e
+            )))
+        })?,
+        Err(_) => LEGACY_PROFILES
+            .iter()
+            .filter_map(|(name, prefix)| {
+                let email = env.var(&format!("{}_EMAIL", prefix)).ok()?;
+                Some(Profile {
+                    name: name.to_string(),
+                    email: email.to_string(),
+                    token_secret: format!("{}_AAS_TOKEN", prefix),
+                    device: None,
+                    locale: None,
+                    country: None,
+                })
+            })
+            .collect(),
+    };
+
+    if !profiles.iter().any(|profile| profile.channel().is_stable()) {
+        return Err(Error::new(ErrorKind::Config(
+            "no 'stable' profile is configured".to_string(),
+        )));
+    }
+
+    Ok(profiles)
+}
@@ -1,4 +1,4 @@
-use googleplay_protobuf::DetailsResponse;
+use googleplay_protobuf::{BulkDetailsEntry, BulkDetailsResponse, DetailsResponse};
 use serde::Serialize;
 
 mod details_response_serde {
@@ -43,7 +43,7 @@ mod details_response_serde {
         state.end()
     }
 
-    struct SerializableItem<'a>(&'a Item);
+    pub(super) struct SerializableItem<'a>(pub(super) &'a Item);
     struct SerializableDiscoveryBadge<'a>(&'a DiscoveryBadge);
     struct SerializableFeatures<'a>(&'a Features);
     struct SerializableFeature<'a>(&'a Feature);
@@ -578,7 +578,123 @@ mod details_response_serde {
     }
 }
 
+mod bulk_details_entry_serde {
+    use super::details_response_serde::SerializableItem;
+    use googleplay_protobuf::BulkDetailsEntry;
+    use serde::ser::{SerializeStruct, Serializer};
+
+    pub fn serialize<S>(entry: &BulkDetailsEntry, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("BulkDetailsEntry", 1)?;
+
+        if let Some(ref item) = entry.item {
+            state.serialize_field("item", &SerializableItem(item))?;
+        }
+
+        state.end()
+    }
+}
+
+#[allow(dead_code)]
+mod bulk_details_response_serde {
+    use super::bulk_details_entry_serde;
+    use googleplay_protobuf::{BulkDetailsEntry, BulkDetailsResponse};
+    use serde::ser::Serializer;
+
+    struct SerializableEntry<'a>(&'a BulkDetailsEntry);
+
+    impl<'a> serde::Serialize for SerializableEntry<'a> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            bulk_details_entry_serde::serialize(self.0, serializer)
+        }
+    }
+
+    pub fn serialize<S>(bulk: &BulkDetailsResponse, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(bulk.entry.iter().map(SerializableEntry))
+    }
+}
+
 #[derive(Serialize)]
 pub struct SerializableDetailsResponse(
     #[serde(with = "details_response_serde")] pub DetailsResponse,
 );
+
+/// Serializes like a `SerializableDetailsResponse` that only carries its `item`.
+#[derive(Serialize)]
+pub struct SerializableBulkDetailsEntry(
+    #[serde(with = "bulk_details_entry_serde")] pub BulkDetailsEntry,
+);
+
+/// Serializes to a list shaped like `SerializableBulkDetailsEntry`, in the order Play returned
+/// the entries. No route serves a whole bulk response yet.
+#[allow(dead_code)]
+#[derive(Serialize)]
+pub struct SerializableBulkDetailsResponse(
+    #[serde(with = "bulk_details_response_serde")] pub BulkDetailsResponse,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googleplay_protobuf::{AppDetails, DocumentDetails, Item};
+
+    fn entry(package_name: &str) -> BulkDetailsEntry {
+        BulkDetailsEntry {
+            item: Some(Item {
+                id: Some(package_name.to_string()),
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        package_name: Some(package_name.to_string()),
+                        version_code: Some(42),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn bulk_entry_matches_details_response() {
+        let entry = entry("com.example.app");
+        let details = DetailsResponse {
+            item: entry.item.clone(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(SerializableBulkDetailsEntry(entry)).unwrap(),
+            serde_json::to_value(SerializableDetailsResponse(details)).unwrap()
+        );
+    }
+
+    #[test]
+    fn bulk_response_lists_entries() {
+        let entries = vec![
+            entry("com.example.app"),
+            BulkDetailsEntry::default(),
+            entry("com.example.other"),
+        ];
+        let bulk = BulkDetailsResponse {
+            entry: entries.clone(),
+        };
+
+        let expected: Vec<serde_json::Value> = entries
+            .into_iter()
+            .map(|entry| serde_json::to_value(SerializableBulkDetailsEntry(entry)).unwrap())
+            .collect();
+        let json = serde_json::to_value(SerializableBulkDetailsResponse(bulk)).unwrap();
+        assert_eq!(json, serde_json::Value::Array(expected));
+        assert_eq!(json[1], serde_json::json!({}));
+        assert_eq!(json[2]["item"]["id"], "com.example.other");
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use worker::{console_log, Date, Env};
+
+const TRACKS_KV_BINDING: &str = "TRACKS";
+/// How long a detected track is trusted before the channel account is asked again.
+const TRACK_AVAILABILITY_TTL_SECS: u64 = 6 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct TrackAvailability {
+    available: bool,
+    checked_at: u64,
+}
+
+fn now() -> u64 {
+    Date::now().as_millis() / 1000
+}
+
+/// Remembers which apps serve a separate build to the accounts of channels other than stable.
+///
+/// Tracks are looked up by the KV key of the client that detected them, as the device and country
+/// a channel logs in with change what it is served. Every key is read on first use and written
+/// back by `flush`, so a lookup of many apps costs two KV operations per channel.
+pub struct TrackCache {
+    env: Env,
+    tracks: HashMap<String, HashMap<String, TrackAvailability>>,
+    changed: HashSet<String>,
+}
+
+impl TrackCache {
+    pub fn new(env: Env) -> Self {
+        Self {
+            env,
+            tracks: HashMap::new(),
+            changed: HashSet::new(),
+        }
+    }
+
+    async fn load(&mut self, key: &str) -> &mut HashMap<String, TrackAvailability> {
+        if !self.tracks.contains_key(key) {
+            let tracks = match self.env.kv(TRACKS_KV_BINDING) {
+                Ok(kv) => match kv.get(key).json().await {
+                    Ok(tracks) => tracks.unwrap_or_default(),
+                    Err(e) => {
+                        console_log!("Failed to load {}: {:?}", key, e);
+                        HashMap::new()
+                    }
+                },
+                Err(_) => HashMap::new(),
+            };
+            self.tracks.insert(key.to_string(), tracks);
+        }
+
+        self.tracks.get_mut(key).unwrap()
+    }
+
+    /// Whether the channel stored under `key` has a track for the app, or `None` if that is not
+    /// known yet.
+    pub async fn get(&mut self, key: &str, package_name: &str) -> Option<bool> {
+        let now = now();
+        self.load(key)
+            .await
+            .get(package_name)
+            .filter(|track| track.checked_at + TRACK_AVAILABILITY_TTL_SECS > now)
+            .map(|track| track.available)
+    }
+
+    pub async fn set(&mut self, key: &str, package_name: &str, available: bool) {
+        let track = TrackAvailability {
+            available,
+            checked_at: now(),
+        };
+        self.load(key).await.insert(package_name.to_string(), track);
+        self.changed.insert(key.to_string());
+    }
+
+    /// Writes the keys that changed back to KV, dropping outdated entries on the way.
+    pub async fn flush(&mut self) {
+        let kv = match self.env.kv(TRACKS_KV_BINDING) {
+            Ok(kv) => kv,
+            Err(_) => return,
+        };
+
+        let now = now();
+        for key in std::mem::take(&mut self.changed) {
+            let tracks = match self.tracks.get_mut(&key) {
+                Some(tracks) => tracks,
+                None => continue,
+            };
+            tracks.retain(|_, track| track.checked_at + TRACK_AVAILABILITY_TTL_SECS > now);
+
+            let result = match kv.put(&key, &*tracks) {
+                Ok(put) => put.execute().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                console_log!("Failed to store {}: {:?}", key, e);
+            }
+        }
+    }
+}